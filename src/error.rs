@@ -0,0 +1,128 @@
+use std::{error, fmt, io, path::PathBuf, process::ExitStatus};
+
+pub type Result<T> = std::result::Result<T, DpmError>;
+
+/// Errors returned by the dpmm engine, so callers can tell failure modes apart.
+#[derive(Debug)]
+pub enum DpmError {
+    /// dpmm.toml doesn't exist at the given path
+    ConfigNotFound(PathBuf),
+    /// A config or generation file isn't valid for its schema
+    ParseError {
+        file: PathBuf,
+        message: String,
+    },
+    /// A manager's command exited unsuccessfully
+    CommandFailed {
+        manager: String,
+        command: String,
+        status: ExitStatus,
+    },
+    /// A manager's command template is empty, `step` being e.g. "installs"
+    EmptyCommand {
+        manager: String,
+        step: String,
+    },
+    /// A manager isn't listed in dpmm.toml, or its file doesn't exist
+    MissingManager(String),
+    /// A manager name pattern matched none of the `available` managers
+    NoMatchingManager {
+        pattern: String,
+        available: Vec<String>,
+    },
+    /// Managers whose `after` constraints form a cycle
+    DependencyCycle(Vec<String>),
+    /// A generation reference doesn't resolve to a stored generation
+    GenerationNotFound(String),
+    /// A manager's `version_check` reported a version older than its `min_version`
+    OutdatedManager {
+        manager: String,
+        found: String,
+        required: String,
+    },
+    /// Several managers failed while being applied concurrently
+    ManagersFailed(Vec<DpmError>),
+    /// A file exists but couldn't be read, e.g. for lack of permissions
+    Read {
+        file: PathBuf,
+        source: io::Error,
+    },
+    Serialize(toml::ser::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for DpmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DpmError::ConfigNotFound(path) => write!(
+                f,
+                "No dpmm.toml found at {}, run `dpmm init` to create one",
+                path.display()
+            ),
+            DpmError::ParseError { file, message } => {
+                write!(f, "Failed to parse {}: {message}", file.display())
+            }
+            DpmError::CommandFailed {
+                manager,
+                command,
+                status,
+            } => write!(f, "{manager} failed running `{command}`: {status}"),
+            DpmError::EmptyCommand { manager, step } => {
+                write!(f, "{manager} has no command to run for {step}")
+            }
+            DpmError::MissingManager(name) => write!(f, "Unknown manager {name}"),
+            DpmError::NoMatchingManager { pattern, available } => write!(
+                f,
+                "No manager matches {pattern}, available managers: {}",
+                available.join(", ")
+            ),
+            DpmError::DependencyCycle(managers) => write!(
+                f,
+                "The `after` ordering of these managers has a cycle: {}",
+                managers.join(", ")
+            ),
+            DpmError::GenerationNotFound(generation) => write!(f, "No generation {generation}"),
+            DpmError::OutdatedManager {
+                manager,
+                found,
+                required,
+            } => write!(
+                f,
+                "{manager} is version {found}, but the config requires at least {required}"
+            ),
+            DpmError::ManagersFailed(errors) => {
+                write!(f, "{} managers failed:", errors.len())?;
+                for e in errors {
+                    write!(f, "\n  {e}")?;
+                }
+                Ok(())
+            }
+            DpmError::Read { file, .. } => write!(f, "Failed to read {}", file.display()),
+            DpmError::Serialize(e) => write!(f, "Failed to serialize: {e}"),
+            DpmError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl error::Error for DpmError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            DpmError::Serialize(e) => Some(e),
+            DpmError::Io(e) => Some(e),
+            DpmError::Read { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DpmError {
+    fn from(e: io::Error) -> Self {
+        DpmError::Io(e)
+    }
+}
+
+impl From<toml::ser::Error> for DpmError {
+    fn from(e: toml::ser::Error) -> Self {
+        DpmError::Serialize(e)
+    }
+}
@@ -0,0 +1,238 @@
+//! A small JSON reader for JSON config files, producing the same values as their TOML
+//! equivalents so both deserialize alike. Members that are `null` are left out, as TOML has
+//! no null and unset options are simply missing.
+
+use toml::{Table, Value};
+
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    value.ok_or_else(|| parser.error("null isn't a valid config"))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> String {
+        let before = &self.chars[..self.pos.min(self.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        format!("{message} at line {line} column {column}")
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{c}`")))
+        }
+    }
+
+    fn literal(&mut self, word: &str) -> bool {
+        let end = self.pos + word.chars().count();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(word.chars()) {
+            self.pos = end;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The next value, `None` for `null`.
+    fn value(&mut self) -> Result<Option<Value>, String> {
+        let value = match self.peek() {
+            Some('{') => Value::Table(self.object()?),
+            Some('[') => Value::Array(self.array()?),
+            Some('"') => Value::String(self.string()?),
+            Some('t') if self.literal("true") => Value::Boolean(true),
+            Some('f') if self.literal("false") => Value::Boolean(false),
+            Some('n') if self.literal("null") => return Ok(None),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number()?,
+            _ => return Err(self.error("expected a value")),
+        };
+        Ok(Some(value))
+    }
+
+    fn object(&mut self) -> Result<Table, String> {
+        self.expect('{')?;
+        let mut table = Table::new();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(table);
+        }
+        loop {
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a member name"));
+            }
+            let key = self.string()?;
+            self.expect(':')?;
+            if let Some(value) = self.value()? {
+                table.insert(key, value);
+            }
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(table);
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Vec<Value>, String> {
+        self.expect('[')?;
+        let mut values = vec![];
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(values);
+        }
+        loop {
+            match self.value()? {
+                Some(value) => values.push(value),
+                None => return Err(self.error("null isn't allowed in arrays")),
+            }
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(values);
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let Some(c) = self.chars.get(self.pos).copied() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let Some(escaped) = self.chars.get(self.pos).copied() else {
+                        return Err(self.error("unterminated string"));
+                    };
+                    self.pos += 1;
+                    s.push(match escaped {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape")),
+                    });
+                }
+                c if (c as u32) < 0x20 => return Err(self.error("control character in string")),
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let end = self.pos + 4;
+        let digits: String = self
+            .chars
+            .get(self.pos..end)
+            .unwrap_or_default()
+            .iter()
+            .collect();
+        let code =
+            u32::from_str_radix(&digits, 16).map_err(|_| self.error("invalid \\u escape"))?;
+        self.pos = end;
+        Ok(code)
+    }
+
+    /// The character of a `\u` escape, combining surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let mut code = self.hex4()?;
+        if (0xd800..0xdc00).contains(&code) && self.literal("\\u") {
+            let low = self.hex4()?;
+            code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+        }
+        char::from_u32(code).ok_or_else(|| self.error("invalid \\u escape"))
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while self
+            .chars
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(*c))
+        {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        if let Ok(n) = text.parse::<i64>() {
+            Ok(Value::Integer(n))
+        } else {
+            text.parse::<f64>()
+                .map(Value::Float)
+                .map_err(|_| self.error("invalid number"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_like_toml() {
+        let json = r#"{"managers": ["apt", "npm"], "jobs": 2, "dry": false, "skip": null}"#;
+        let toml: Value =
+            toml::from_str("managers = [\"apt\", \"npm\"]\njobs = 2\ndry = false").unwrap();
+        assert_eq!(parse(json).unwrap(), toml);
+    }
+
+    #[test]
+    fn parses_escapes() {
+        let value = parse(r#"{"s": "a\"b\\né😀"}"#).unwrap();
+        assert_eq!(value["s"].as_str(), Some("a\"b\\né😀"));
+        assert_eq!(parse("[1.5, -2]").unwrap()[0].as_float(), Some(1.5));
+    }
+
+    #[test]
+    fn reports_error_position() {
+        assert_eq!(
+            parse("{\n  \"a\": }").unwrap_err(),
+            "expected a value at line 2 column 8"
+        );
+        assert!(
+            parse("{} x")
+                .unwrap_err()
+                .starts_with("trailing characters")
+        );
+        assert!(parse("null").is_err());
+    }
+}
@@ -0,0 +1,4174 @@
+mod error;
+mod json;
+pub mod preset;
+pub mod schema;
+mod yaml;
+
+pub use error::{DpmError, Result};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    collections::{BTreeMap, HashSet},
+    env,
+    ffi::OsStr,
+    fmt, fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Set once the user presses Ctrl-C, or a child dies of it. The running child receives the
+/// signal as well, so we let it finish, then stop before running anything else or recording
+/// state.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[cfg(not(windows))]
+pub fn install_interrupt_handler() {
+    const SIGINT: i32 = 2;
+    unsafe extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        fn siginterrupt(signum: i32, flag: i32) -> i32;
+    }
+    extern "C" fn on_sigint(_: i32) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+    unsafe {
+        signal(SIGINT, on_sigint);
+        // `signal` restarts interrupted reads, which would leave a prompt waiting for input
+        siginterrupt(SIGINT, 1);
+    }
+}
+
+/// Whether the child was killed by Ctrl-C, rather than failing on its own.
+#[cfg(not(windows))]
+pub fn killed_by_interrupt(status: &ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal() == Some(2)
+}
+
+#[cfg(windows)]
+pub fn killed_by_interrupt(status: &ExitStatus) -> bool {
+    // STATUS_CONTROL_C_EXIT
+    status.code() == Some(0xC000013Au32 as i32)
+}
+
+#[cfg(windows)]
+pub fn install_interrupt_handler() {
+    unsafe extern "system" {
+        fn SetConsoleCtrlHandler(handler: extern "system" fn(u32) -> i32, add: i32) -> i32;
+    }
+    extern "system" fn on_ctrl(_: u32) -> i32 {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        1
+    }
+    unsafe {
+        SetConsoleCtrlHandler(on_ctrl, 1);
+    }
+}
+
+/// Version of the dpmm.toml and generation formats, bumped along with a migration whenever an
+/// existing field changes meaning. Files without one predate versioning, and are version 0.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Migrations of dpmm.toml, the one at index N bringing `schema_version` N to N + 1. Version 1
+/// reads unversioned configs as they are.
+const CONFIG_MIGRATIONS: [fn(&mut toml_edit::DocumentMut); 1] = [|_| ()];
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dpmm {
+    /// Format version the file is written in, see [`SCHEMA_VERSION`]
+    pub schema_version: Option<u32>,
+    /// Either a list of names, or a table of names to [`ManagerOptions`]. Entries with an
+    /// `install` command or a `preset` define the manager inline instead of in its own file
+    #[serde(default, with = "manager_entries")]
+    pub managers: Vec<ManagerEntry>,
+    /// Git repository whose checkout is used as the config directory instead, so the rest of
+    /// this file is ignored
+    pub source: Option<String>,
+    /// Default for `switch --max-removals`
+    pub max_removals: Option<usize>,
+    /// Default for `--host-prefix`
+    pub host_prefix: Option<String>,
+    /// Default `env_file` of the managers
+    pub env_file: Option<PathBuf>,
+    /// Package groups of the managers that are used, overridden by `--group`
+    pub groups: Option<Vec<String>>,
+    /// Files mapping manager names to packages added to theirs, see [`include_paths`]
+    pub include: Option<Vec<String>>,
+    /// Run in the config directory before a switch loads the config, see [`run_hook`]
+    pub pre_switch: Option<CommandTemplate>,
+    /// Run in the config directory after a switch succeeded
+    pub post_switch: Option<CommandTemplate>,
+    /// What a switch or check does about packages declared by several managers, defaults to warn
+    pub duplicate_packages: Option<DuplicatePolicy>,
+    /// Values of the `{vars.<name>}` references in the managers, see [`Dpm::apply_vars`]
+    pub vars: Option<BTreeMap<String, String>>,
+    /// Default for `--profile`
+    pub profile: Option<String>,
+    /// Manager sets selected with `--profile <name>`, e.g. `[profiles.work]`
+    pub profiles: Option<BTreeMap<String, DpmmProfile>>,
+}
+
+/// The managers and groups used under a profile. Their own `[profiles.<name>]` overrides
+/// apply too.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DpmmProfile {
+    /// Managers of dpmm.toml that are used, unset for all of them
+    pub managers: Option<Vec<String>>,
+    /// Package groups used instead of dpmm.toml's, overridden by `--group`
+    pub groups: Option<Vec<String>>,
+}
+
+/// Where a package is installed, picking the manager's `install_user` or `install_system`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    User,
+    System,
+}
+
+/// The scopes packages are resolved in, `None` being the manager's plain commands.
+const SCOPES: [Option<Scope>; 3] = [None, Some(Scope::User), Some(Scope::System)];
+
+/// How packages of the same name declared by several managers are treated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    Allow,
+    #[default]
+    Warn,
+    Error,
+}
+
+impl Dpmm {
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.managers.iter().map(|m| m.name.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names().any(|m| m == name)
+    }
+}
+
+/// A manager listed in dpmm.toml.
+#[derive(Clone, Debug, Default)]
+pub struct ManagerEntry {
+    pub name: String,
+    pub options: ManagerOptions,
+    /// The manager's definition when it's written in dpmm.toml rather than in its own file
+    pub inline: Option<Box<Dpm>>,
+}
+
+impl From<&str> for ManagerEntry {
+    fn from(name: &str) -> Self {
+        ManagerEntry {
+            name: name.to_string(),
+            options: ManagerOptions::default(),
+            inline: None,
+        }
+    }
+}
+
+/// Options set for a manager in dpmm.toml, overriding those of its file.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManagerOptions {
+    pub enabled: Option<bool>,
+    pub after: Option<Vec<String>>,
+    pub os: Option<Vec<String>>,
+}
+
+impl ManagerOptions {
+    pub fn apply(&self, manager: &mut Dpm) {
+        if self.enabled.is_some() {
+            manager.enabled = self.enabled;
+        }
+        if self.after.is_some() {
+            manager.after.clone_from(&self.after);
+        }
+        if self.os.is_some() {
+            manager.os.clone_from(&self.os);
+        }
+    }
+}
+
+/// (De)serializes dpmm.toml's managers in either form, keeping their order.
+/// They're written back as a list unless some manager has options, and as an array of
+/// tables when some manager is defined inline.
+mod manager_entries {
+    use super::{Dpm, ManagerEntry, ManagerOptions};
+    use serde::{
+        Deserializer, Serialize, Serializer,
+        de::{Error, MapAccess, SeqAccess, Visitor},
+        ser::SerializeMap,
+    };
+    use std::fmt;
+    use toml::{Table, Value};
+
+    #[derive(Serialize)]
+    #[serde(untagged)]
+    enum Element<'a> {
+        Name(&'a str),
+        Options {
+            name: &'a str,
+            #[serde(flatten)]
+            options: &'a ManagerOptions,
+        },
+        Inline(&'a Dpm),
+    }
+
+    pub fn serialize<S: Serializer>(
+        entries: &[ManagerEntry],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if entries.iter().any(|e| e.inline.is_some()) {
+            return serializer.collect_seq(entries.iter().map(|e| match &e.inline {
+                Some(dpm) => Element::Inline(dpm),
+                None if e.options == ManagerOptions::default() => Element::Name(&e.name),
+                None => Element::Options {
+                    name: &e.name,
+                    options: &e.options,
+                },
+            }));
+        }
+        if entries
+            .iter()
+            .all(|e| e.options == ManagerOptions::default())
+        {
+            return serializer.collect_seq(entries.iter().map(|e| &e.name));
+        }
+        let mut map = serializer.serialize_map(Some(entries.len()))?;
+        for e in entries {
+            map.serialize_entry(&e.name, &e.options)?;
+        }
+        map.end()
+    }
+
+    struct EntriesVisitor;
+
+    impl<'de> Visitor<'de> for EntriesVisitor {
+        type Value = Vec<ManagerEntry>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a list of manager names or a table of manager options")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut entries = vec![];
+            while let Some(value) = seq.next_element::<Value>()? {
+                entries.push(match value {
+                    Value::String(name) => ManagerEntry::from(name.as_str()),
+                    Value::Table(mut table) => {
+                        let Some(Value::String(name)) = table.remove("name") else {
+                            return Err(A::Error::custom("managers in a list need a `name`"));
+                        };
+                        entry(name, table)?
+                    }
+                    _ => return Err(A::Error::custom("expected a manager name or table")),
+                });
+            }
+            Ok(entries)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut entries = vec![];
+            while let Some((name, table)) = map.next_entry::<String, Table>()? {
+                entries.push(entry(name, table)?);
+            }
+            Ok(entries)
+        }
+    }
+
+    /// A manager's table, which defines it inline when it has an `install` command, a `preset`
+    /// or `extends`, and otherwise holds options for its file.
+    fn entry<E: Error>(name: String, mut table: Table) -> Result<ManagerEntry, E> {
+        if !["install", "preset", "extends"]
+            .iter()
+            .any(|key| table.contains_key(*key))
+        {
+            let options = Value::Table(table).try_into().map_err(E::custom)?;
+            return Ok(ManagerEntry {
+                name,
+                options,
+                inline: None,
+            });
+        }
+        table.insert("name".to_string(), Value::String(name.clone()));
+        let dpm: Dpm = Value::Table(table).try_into().map_err(E::custom)?;
+        Ok(ManagerEntry {
+            name,
+            options: ManagerOptions::default(),
+            inline: Some(Box::new(dpm)),
+        })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<ManagerEntry>, D::Error> {
+        deserializer.deserialize_any(EntriesVisitor)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dpm {
+    pub name: Option<String>,
+    /// Built-in commands filling in those left unset, see [`Dpm::apply_preset`]
+    pub preset: Option<String>,
+    /// Manager, or preset, whose commands and options this one inherits, see [`Dpm::inherit`]
+    pub extends: Option<String>,
+    pub update: Option<CommandTemplate>,
+    pub upgrade: Option<CommandTemplate>,
+    /// Only optional in files with a `preset`
+    #[serde(default, skip_serializing_if = "CommandTemplate::is_empty")]
+    pub install: CommandTemplate,
+    /// Unset for managers that can't remove packages, whose removals are skipped
+    pub uninstall: Option<CommandTemplate>,
+    /// Default scope of the packages, picking the commands below over install and uninstall
+    pub scope: Option<Scope>,
+    /// Installs the packages of the user scope, defaults to `install`
+    pub install_user: Option<CommandTemplate>,
+    /// Installs the packages of the system scope, defaults to `install`
+    pub install_system: Option<CommandTemplate>,
+    /// Uninstalls the packages of the user scope, defaults to `uninstall`
+    pub uninstall_user: Option<CommandTemplate>,
+    /// Uninstalls the packages of the system scope, defaults to `uninstall`
+    pub uninstall_system: Option<CommandTemplate>,
+    /// Run before a switch's installs of this manager, with `$` substituted by the packages
+    pub pre_install: Option<CommandTemplate>,
+    /// Run after a switch's installs of this manager succeeded
+    pub post_install: Option<CommandTemplate>,
+    /// Run before a switch's uninstalls of this manager
+    pub pre_uninstall: Option<CommandTemplate>,
+    /// Run after a switch's uninstalls of this manager succeeded
+    pub post_uninstall: Option<CommandTemplate>,
+    /// Adds one of `repos`, with `$` substituted by its words
+    pub add_repo: Option<CommandTemplate>,
+    /// Removes a repository dropped from `repos`
+    pub remove_repo: Option<CommandTemplate>,
+    pub supports_multi_args: Option<bool>,
+    /// With multi args, the most packages passed to a single install/uninstall
+    pub batch_size: Option<usize>,
+    /// Exit codes of the manager's commands that should be treated as success
+    pub ignore_exit_codes: Option<Vec<i32>>,
+    /// Query run per package before installing it, exiting 0 means it's already installed
+    pub is_installed: Option<String>,
+    /// Command printing the installed packages one per line, used by `reconcile`
+    pub list_installed: Option<String>,
+    /// Command printing the available packages one per line, expanding wildcard packages
+    pub list_available: Option<String>,
+    /// Command printing the manager's version, checked against `min_version`
+    pub version_check: Option<String>,
+    /// Oldest version of the manager a switch runs its commands with, see [`check_version`]
+    pub min_version: Option<String>,
+    /// File of `KEY=VALUE` lines set in the environment of this manager's commands, e.g. tokens
+    pub env_file: Option<PathBuf>,
+    /// Variables set in the environment of this manager's commands, before those of `env_file`
+    pub env: Option<BTreeMap<String, String>>,
+    /// Working directory of this manager's commands
+    pub cwd: Option<PathBuf>,
+    /// Whether `update all`/`upgrade all` include this manager, defaults to true
+    pub include_in_all: Option<bool>,
+    /// Ask before running this manager's uninstalls, when interactive
+    pub confirm_removals: Option<bool>,
+    /// Whether switch, update and upgrade handle this manager, defaults to true
+    pub enabled: Option<bool>,
+    /// Managers whose changes must be applied before this one's
+    pub after: Option<Vec<String>>,
+    /// Operating systems, as in `std::env::consts::OS`, this manager is used on. Unset means all
+    pub os: Option<Vec<String>>,
+    /// Command whose newline-separated stdout is appended to `packages` on every run
+    pub packages_cmd: Option<String>,
+    /// Files whose `packages` are added to the manager's, see [`include_paths`]
+    pub include: Option<Vec<String>>,
+    /// Repositories, taps or remotes the packages come from, see [`add_repos`]
+    pub repos: Option<Vec<String>>,
+    pub packages: Vec<Package>,
+    /// Packages a switch uninstalls whenever they're installed, see [`remove_absent`]
+    pub absent: Option<Vec<String>>,
+    /// Named sets of packages, e.g. `[groups.dev]`, added to `packages` when selected
+    pub groups: Option<BTreeMap<String, Group>>,
+    /// Package overrides applied on the machine with the given hostname
+    pub hosts: Option<BTreeMap<String, Profile>>,
+    /// Package overrides applied with `--profile <name>`
+    pub profiles: Option<BTreeMap<String, Profile>>,
+}
+
+/// A named set of packages of a manager, used when its name is selected.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    pub packages: Vec<Package>,
+}
+
+/// Packages a manager uses under a profile, e.g. `[profiles.server]`, or on a host,
+/// e.g. `[hosts.laptop]`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Replace the base packages instead of extending them, defaults to false
+    pub replace: Option<bool>,
+    pub packages: Vec<Package>,
+}
+
+/// A `packages` entry, either a string, `"ripgrep"` or `"ripgrep@14.1.0"`, or a table,
+/// `{ name = "ripgrep", version = "14.1.0" }`, which can also hold options. Generations record
+/// it as a string when it has none.
+///
+/// Packages are the same package when their names and versions are, whatever their options,
+/// see [`Package::same_entry`] to compare those too.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(remote = "Self", deny_unknown_fields)]
+pub struct Package {
+    pub name: String,
+    /// Version to pin, unset for whatever the manager installs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Operating systems, as in `std::env::consts::OS`, the package is used on. Unset means all
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "one_or_many")]
+    pub os: Option<Vec<String>>,
+    /// CPU architectures, as in `std::env::consts::ARCH`, the package is used on. Unset means all
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "one_or_many")]
+    pub arch: Option<Vec<String>>,
+    /// Platforms, see [`matches_platform`], the package is used on. Unset means all
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "one_or_many")]
+    pub only: Option<Vec<String>>,
+    /// Platforms the package isn't used on
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "one_or_many")]
+    pub exclude: Option<Vec<String>>,
+    /// Extra arguments of the install command of this package, which is then installed alone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<CommandTemplate>,
+    /// The manager's name for the package, passed to its commands instead of `name`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_as: Option<String>,
+    /// Why the package is installed, shown by `dpmm why`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Scope the package is installed in, overriding the manager's `scope`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Scope>,
+    /// Whether a failed install only warns, leaving the package unrecorded for the next switch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub optional: Option<bool>,
+    /// Free-form labels, shown by `dpmm why`
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "one_or_many")]
+    pub tags: Option<Vec<String>>,
+}
+
+impl Package {
+    /// Parses `name@version`, splitting at the last `@` so scoped names like `@types/node`
+    /// keep theirs.
+    pub fn parse(spec: &str) -> Self {
+        match spec.rsplit_once('@') {
+            Some((name, version)) if !name.is_empty() && !version.is_empty() => Package {
+                name: name.to_string(),
+                version: Some(version.to_string()),
+                ..Default::default()
+            },
+            _ => Package {
+                name: spec.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// `name@version`, or just the name, as passed for `$` and recorded in generations.
+    pub fn spec(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{}@{version}", self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Whether the package is meant for this OS and architecture.
+    pub fn is_active(&self) -> bool {
+        self.os
+            .as_ref()
+            .is_none_or(|os| os.iter().any(|os| os == env::consts::OS))
+            && self
+                .arch
+                .as_ref()
+                .is_none_or(|arch| arch.iter().any(|arch| arch == env::consts::ARCH))
+            && self
+                .only
+                .as_ref()
+                .is_none_or(|only| only.iter().any(|p| matches_platform(p)))
+            && !self.exclude.iter().flatten().any(|p| matches_platform(p))
+    }
+
+    fn has_options(&self) -> bool {
+        self.os.is_some()
+            || self.arch.is_some()
+            || self.only.is_some()
+            || self.exclude.is_some()
+            || self.args.is_some()
+            || self.installed_as.is_some()
+            || self.scope.is_some()
+            || self.optional.is_some()
+            || self.reason.is_some()
+            || self.tags.is_some()
+    }
+
+    /// Whether both entries install the package alike: the same spec, arguments, name in the
+    /// manager and scope.
+    pub fn installs_alike(&self, other: &Package) -> bool {
+        self == other
+            && self.args == other.args
+            && self.installed_as == other.installed_as
+            && self.scope == other.scope
+    }
+
+    /// Whether both entries are identical, options included.
+    pub fn same_entry(&self, other: &Package) -> bool {
+        self.installs_alike(other)
+            && self.os == other.os
+            && self.arch == other.arch
+            && self.only == other.only
+            && self.exclude == other.exclude
+            && self.reason == other.reason
+            && self.optional == other.optional
+            && self.tags == other.tags
+    }
+
+    /// The name the manager knows the package by, see `installed_as`.
+    pub fn installed_name(&self) -> &str {
+        self.installed_as.as_ref().unwrap_or(&self.name)
+    }
+
+    /// [`Package::spec`] with the name the manager knows the package by, see `installed_as`.
+    pub fn installed_spec(&self) -> String {
+        let name = self.installed_name();
+        match &self.version {
+            Some(version) => format!("{name}@{version}"),
+            None => name.to_string(),
+        }
+    }
+}
+
+/// Whether `platform` names this machine's OS, e.g. `linux`, its architecture, e.g. `aarch64`,
+/// or both, `linux-x86_64`, as in `std::env::consts`.
+pub fn matches_platform(platform: &str) -> bool {
+    let (os, arch) = (env::consts::OS, env::consts::ARCH);
+    platform == os || platform == arch || platform == format!("{os}-{arch}")
+}
+
+impl PartialEq for Package {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.name, &self.version) == (&other.name, &other.version)
+    }
+}
+
+impl Eq for Package {}
+
+impl std::hash::Hash for Package {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (&self.name, &self.version).hash(state);
+    }
+}
+
+impl PartialOrd for Package {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Package {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (&self.name, &self.version).cmp(&(&other.name, &other.version))
+    }
+}
+
+impl fmt::Display for Package {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec())
+    }
+}
+
+impl From<&str> for Package {
+    fn from(spec: &str) -> Self {
+        Package::parse(spec)
+    }
+}
+
+impl From<String> for Package {
+    fn from(spec: String) -> Self {
+        Package::parse(&spec)
+    }
+}
+
+mod package_entry {
+    use super::Package;
+    use serde::{
+        Deserialize, Deserializer, Serialize, Serializer,
+        de::{self, MapAccess, Visitor, value::MapAccessDeserializer},
+    };
+    use std::fmt;
+
+    impl Serialize for Package {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            if self.has_options() {
+                Package::serialize(self, serializer)
+            } else {
+                serializer.serialize_str(&self.spec())
+            }
+        }
+    }
+
+    struct PackageVisitor;
+
+    impl<'de> Visitor<'de> for PackageVisitor {
+        type Value = Package;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a package name or a table with its name")
+        }
+
+        fn visit_str<E: de::Error>(self, spec: &str) -> Result<Self::Value, E> {
+            Ok(Package::parse(spec))
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            Package::deserialize(MapAccessDeserializer::new(map))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Package {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(PackageVisitor)
+        }
+    }
+}
+
+/// An optional list that can also be written as a single string, e.g. `os = "linux"`.
+mod one_or_many {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        values: &Option<Vec<String>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        values.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<String>>, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum OneOrMany {
+            One(String),
+            Many(Vec<String>),
+        }
+        Ok(Some(match OneOrMany::deserialize(deserializer)? {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }))
+    }
+}
+
+/// The specs of `packages`, see [`Package::spec`].
+pub fn package_specs(packages: &[Package]) -> Vec<String> {
+    packages.iter().map(Package::spec).collect()
+}
+
+/// A command template, either a string split on whitespace or an explicit argv, which avoids
+/// any ambiguity for arguments containing spaces.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum CommandTemplate {
+    Line(String),
+    Argv(Vec<String>),
+}
+
+impl Default for CommandTemplate {
+    fn default() -> Self {
+        CommandTemplate::Line(String::new())
+    }
+}
+
+impl From<&str> for CommandTemplate {
+    fn from(s: &str) -> Self {
+        CommandTemplate::Line(s.to_string())
+    }
+}
+
+impl From<String> for CommandTemplate {
+    fn from(s: String) -> Self {
+        CommandTemplate::Line(s)
+    }
+}
+
+impl From<Vec<String>> for CommandTemplate {
+    fn from(argv: Vec<String>) -> Self {
+        CommandTemplate::Argv(argv)
+    }
+}
+
+impl CommandTemplate {
+    /// The template without its `${VAR}` references, to look for placeholders in.
+    fn placeholder_text(&self) -> String {
+        match self {
+            CommandTemplate::Line(line) => interpolate(line, str::to_string, false),
+            CommandTemplate::Argv(argv) => argv
+                .iter()
+                .map(|arg| interpolate(arg, str::to_string, false))
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+
+    /// The template with `args` appended, e.g. a package's own install flags.
+    pub fn with_args(&self, args: &CommandTemplate) -> CommandTemplate {
+        match (self, args) {
+            (CommandTemplate::Line(line), CommandTemplate::Line(args)) => {
+                CommandTemplate::Line(format!("{line} {args}"))
+            }
+            _ => CommandTemplate::Argv([self.words(), args.words()].concat()),
+        }
+    }
+
+    fn words(&self) -> Vec<String> {
+        match self {
+            CommandTemplate::Line(line) => line.split_whitespace().map(str::to_string).collect(),
+            CommandTemplate::Argv(argv) => argv.clone(),
+        }
+    }
+
+    /// Whether the template has no program to run.
+    pub fn is_empty(&self) -> bool {
+        self.argv("", &[]).is_empty()
+    }
+
+    /// Whether the template uses the named placeholders, in which `$` is a plain dollar sign.
+    pub fn is_named(&self) -> bool {
+        let text = self.placeholder_text();
+        NAMED_PLACEHOLDERS.iter().any(|name| mentions(&text, name))
+    }
+
+    /// Whether the template takes packages, with `$`, `{packages}` or `{package}`.
+    pub fn has_placeholder(&self) -> bool {
+        let text = self.placeholder_text();
+        if self.is_named() {
+            mentions(&text, "packages") || mentions(&text, "package")
+        } else {
+            text.contains('$')
+        }
+    }
+
+    /// Whether the template takes the version of a pinned package with `$version` or
+    /// `{version}`.
+    pub fn has_version_placeholder(&self) -> bool {
+        let text = self.placeholder_text();
+        if self.is_named() {
+            mentions(&text, "version")
+        } else {
+            text.contains("$version")
+        }
+    }
+
+    /// Whether the template takes a single package at a time, with `{package}`.
+    pub fn takes_one_package(&self) -> bool {
+        self.is_named() && mentions(&self.placeholder_text(), "package")
+    }
+
+    /// The argv to run with `$` substituted by `packages`. In the array form an element that is
+    /// exactly `$` expands to one argument per package.
+    ///
+    /// With a `$version` placeholder, a single pinned package is passed as its name with
+    /// `$version` substituted by its version. Otherwise `$version` is left out along with what
+    /// ties it to the package, so `$==$version` and `$@$version` pass just the packages, while
+    /// a flag taking the version, `--version=$version` or `--version $version`, is left out
+    /// whole, see [`Package::spec`].
+    ///
+    /// Templates using any of the named placeholders `{packages}`, `{package}`, `{version}` and
+    /// `{manager}` take those instead of `$` and `$version`, and `{{`/`}}` are literal braces.
+    /// `{manager}` is substituted by `manager`.
+    ///
+    /// `${VAR}` is substituted by the variable's value in dpmm's environment, and left as
+    /// written when it's unset.
+    pub fn argv(&self, manager: &str, packages: &[String]) -> Vec<String> {
+        let mut packages: Vec<String> = packages
+            .iter()
+            .filter(|p| !p.trim().is_empty())
+            .cloned()
+            .collect();
+        let mut version = None;
+        if self.has_version_placeholder()
+            && let [p] = packages.as_mut_slice()
+        {
+            let package = Package::parse(p);
+            *p = package.name;
+            version = package.version;
+        }
+        let joined = packages.join(" ");
+        let named = self.is_named();
+        let substitute = |s: &str| {
+            if named {
+                replace_named(s, |name| match name {
+                    "packages" | "package" => Some(joined.clone()),
+                    "version" => version.clone(),
+                    "manager" => Some(manager.to_string()),
+                    _ => None,
+                })
+            } else {
+                match &version {
+                    Some(version) => s.replace("$version", version).replace("$", &joined),
+                    None => s.replace("$", &joined),
+                }
+            }
+        };
+        let expand = |arg: &str| interpolate(arg, substitute, true);
+        let all_packages = if named { "{packages}" } else { "$" };
+        let words = if version.is_some() {
+            self.words()
+        } else {
+            without_version(self.words(), named)
+        };
+        match self {
+            CommandTemplate::Line(_) => words
+                .iter()
+                .map(|arg| expand(arg))
+                .flat_map(|arg| {
+                    arg.split_whitespace()
+                        .map(str::to_string)
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+            CommandTemplate::Argv(_) => words
+                .iter()
+                .flat_map(|arg| {
+                    if arg == all_packages {
+                        packages.clone()
+                    } else {
+                        vec![expand(arg)]
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The template `words` without their version placeholder, for packages that aren't pinned.
+/// The `=`, `@` or `:` joining it to a package goes with it, and so does a flag taking it as
+/// its value.
+fn without_version(words: Vec<String>, named: bool) -> Vec<String> {
+    let placeholder = if named { "{version}" } else { "$version" };
+    let mut out: Vec<String> = vec![];
+    for word in words {
+        let Some(i) = word.find(placeholder) else {
+            out.push(word);
+            continue;
+        };
+        let prefix = word[..i].trim_end_matches(['=', '@', ':']);
+        let stripped = format!("{prefix}{}", &word[i + placeholder.len()..]);
+        if stripped.is_empty() {
+            if out.last().is_some_and(|flag| flag.starts_with('-')) {
+                out.pop();
+            }
+        } else if !stripped.starts_with('-') {
+            out.push(stripped);
+        }
+    }
+    out
+}
+
+/// Replaces each `{vars.<name>}` in `text` by the value of `name` in `vars`. `{{vars.<name>}}`
+/// is left as written.
+pub fn substitute_vars(
+    text: &str,
+    vars: &BTreeMap<String, String>,
+) -> std::result::Result<String, String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{vars.") {
+        if rest[..start].ends_with('{') {
+            out.push_str(&rest[..start + 1]);
+            rest = &rest[start + 1..];
+            continue;
+        }
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + "{vars.".len()..start + len];
+        let value = vars
+            .get(name)
+            .ok_or_else(|| format!("unknown variable vars.{name}"))?;
+        out.push_str(&rest[..start]);
+        out.push_str(value);
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+const NAMED_PLACEHOLDERS: [&str; 4] = ["packages", "package", "version", "manager"];
+
+/// Replaces each `{name}` in `text` by `value(name)`, leaving it as written when that's `None`.
+/// `{{` and `}}` are literal braces.
+fn replace_named(text: &str, mut value: impl FnMut(&str) -> Option<String>) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+        } else if tail.starts_with('{')
+            && let Some(end) = tail.find('}')
+            && let Some(v) = value(&tail[1..end])
+        {
+            out.push_str(&v);
+            rest = &tail[end + 1..];
+        } else {
+            out.push_str(&tail[..1]);
+            rest = &tail[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Whether `text` has the named placeholder `{name}`, escaped braces aside.
+fn mentions(text: &str, name: &str) -> bool {
+    let mut found = false;
+    replace_named(text, |n| {
+        found |= n == name;
+        None
+    });
+    found
+}
+
+/// Maps the text of `arg` around its `${VAR}` references with `literal`. The references are
+/// substituted by the variables' values with `expand_vars`, and left out otherwise.
+fn interpolate(arg: &str, literal: impl Fn(&str) -> String, expand_vars: bool) -> String {
+    let mut out = String::new();
+    let mut rest = arg;
+    while let Some(start) = rest.find("${")
+        && let Some(len) = rest[start..].find('}')
+    {
+        out.push_str(&literal(&rest[..start]));
+        let reference = &rest[start..start + len + 1];
+        if expand_vars {
+            match env::var(&reference[2..len]) {
+                Ok(value) => out.push_str(&value),
+                Err(_) => out.push_str(reference),
+            }
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(&literal(rest));
+    out
+}
+
+impl Dpm {
+    pub fn builder() -> DpmBuilder {
+        DpmBuilder::default()
+    }
+
+    /// Substitutes the `{vars.<name>}` references in the manager's commands and packages by
+    /// the values of `vars`, erroring for names it doesn't have.
+    pub fn apply_vars(
+        &mut self,
+        vars: &BTreeMap<String, String>,
+    ) -> std::result::Result<(), String> {
+        let mut texts: Vec<&mut String> = vec![];
+        let templates = [
+            &mut self.update,
+            &mut self.upgrade,
+            &mut self.uninstall,
+            &mut self.install_user,
+            &mut self.install_system,
+            &mut self.uninstall_user,
+            &mut self.uninstall_system,
+            &mut self.pre_install,
+            &mut self.post_install,
+            &mut self.pre_uninstall,
+            &mut self.post_uninstall,
+            &mut self.add_repo,
+            &mut self.remove_repo,
+        ];
+        let templates = templates.into_iter().flatten().chain([&mut self.install]);
+        for t in templates {
+            match t {
+                CommandTemplate::Line(line) => texts.push(line),
+                CommandTemplate::Argv(argv) => texts.extend(argv.iter_mut()),
+            }
+        }
+        texts.extend(self.is_installed.iter_mut());
+        texts.extend(self.list_installed.iter_mut());
+        texts.extend(self.version_check.iter_mut());
+        texts.extend(self.list_available.iter_mut());
+        texts.extend(self.packages_cmd.iter_mut());
+        texts.extend(self.absent.iter_mut().flatten());
+        texts.extend(self.repos.iter_mut().flatten());
+        let packages = self
+            .packages
+            .iter_mut()
+            .chain(
+                self.groups
+                    .iter_mut()
+                    .flatten()
+                    .flat_map(|(_, g)| &mut g.packages),
+            )
+            .chain(
+                self.hosts
+                    .iter_mut()
+                    .chain(self.profiles.iter_mut())
+                    .flatten()
+                    .flat_map(|(_, p)| &mut p.packages),
+            );
+        for p in packages {
+            texts.push(&mut p.name);
+            texts.extend(p.version.iter_mut());
+            texts.extend(p.installed_as.iter_mut());
+            match &mut p.args {
+                Some(CommandTemplate::Line(line)) => texts.push(line),
+                Some(CommandTemplate::Argv(argv)) => texts.extend(argv.iter_mut()),
+                None => (),
+            }
+        }
+        for text in texts {
+            *text = substitute_vars(text, vars)?;
+        }
+        Ok(())
+    }
+
+    /// The install command of packages in `scope`.
+    pub fn install_in(&self, scope: Option<Scope>) -> &CommandTemplate {
+        match scope {
+            Some(Scope::User) => self.install_user.as_ref(),
+            Some(Scope::System) => self.install_system.as_ref(),
+            None => None,
+        }
+        .unwrap_or(&self.install)
+    }
+
+    /// The uninstall command of packages in `scope`, if the manager can remove them.
+    pub fn uninstall_in(&self, scope: Option<Scope>) -> Option<&CommandTemplate> {
+        match scope {
+            Some(Scope::User) => self.uninstall_user.as_ref(),
+            Some(Scope::System) => self.uninstall_system.as_ref(),
+            None => None,
+        }
+        .or(self.uninstall.as_ref())
+    }
+
+    /// Fills in the commands and options the manager leaves unset from `base`, the manager it
+    /// extends. Packages, groups, overrides and whether it's enabled aren't inherited.
+    pub fn inherit(&mut self, base: Dpm) {
+        if self.install.is_empty() {
+            self.install = base.install;
+        }
+        self.update = self.update.take().or(base.update);
+        self.upgrade = self.upgrade.take().or(base.upgrade);
+        self.uninstall = self.uninstall.take().or(base.uninstall);
+        self.scope = self.scope.take().or(base.scope);
+        self.install_user = self.install_user.take().or(base.install_user);
+        self.install_system = self.install_system.take().or(base.install_system);
+        self.uninstall_user = self.uninstall_user.take().or(base.uninstall_user);
+        self.uninstall_system = self.uninstall_system.take().or(base.uninstall_system);
+        self.pre_install = self.pre_install.take().or(base.pre_install);
+        self.post_install = self.post_install.take().or(base.post_install);
+        self.pre_uninstall = self.pre_uninstall.take().or(base.pre_uninstall);
+        self.post_uninstall = self.post_uninstall.take().or(base.post_uninstall);
+        self.add_repo = self.add_repo.take().or(base.add_repo);
+        self.remove_repo = self.remove_repo.take().or(base.remove_repo);
+        self.supports_multi_args = self.supports_multi_args.take().or(base.supports_multi_args);
+        self.batch_size = self.batch_size.take().or(base.batch_size);
+        self.ignore_exit_codes = self.ignore_exit_codes.take().or(base.ignore_exit_codes);
+        self.is_installed = self.is_installed.take().or(base.is_installed);
+        self.list_installed = self.list_installed.take().or(base.list_installed);
+        self.version_check = self.version_check.take().or(base.version_check);
+        self.list_available = self.list_available.take().or(base.list_available);
+        self.min_version = self.min_version.take().or(base.min_version);
+        self.env_file = self.env_file.take().or(base.env_file);
+        self.env = self.env.take().or(base.env);
+        self.cwd = self.cwd.take().or(base.cwd);
+        self.include_in_all = self.include_in_all.take().or(base.include_in_all);
+        self.confirm_removals = self.confirm_removals.take().or(base.confirm_removals);
+        self.os = self.os.take().or(base.os);
+    }
+
+    /// Fills in the commands the manager leaves unset from its `preset`, which is then cleared
+    /// like a file that spelled them out. Errors for unknown presets, and for managers left
+    /// without an install command.
+    pub fn apply_preset(&mut self) -> std::result::Result<(), String> {
+        if let Some(name) = self.preset.take() {
+            let preset = preset::find(&name).ok_or_else(|| {
+                let names: Vec<_> = preset::PRESETS.iter().map(|p| p.name).collect();
+                format!(
+                    "Unknown preset {name}, available presets: {}",
+                    names.join(", ")
+                )
+            })?;
+            if self.install.is_empty() {
+                self.install = preset.install.into();
+            }
+            if self.update.is_none() {
+                self.update = preset.update.map(CommandTemplate::from);
+            }
+            self.upgrade.get_or_insert_with(|| preset.upgrade.into());
+            self.uninstall
+                .get_or_insert_with(|| preset.uninstall.into());
+            if self.list_installed.is_none() {
+                self.list_installed = preset.list_installed.map(str::to_string);
+            }
+            if self.add_repo.is_none() {
+                self.add_repo = preset.add_repo.map(CommandTemplate::from);
+            }
+            if self.remove_repo.is_none() {
+                self.remove_repo = preset.remove_repo.map(CommandTemplate::from);
+            }
+            self.supports_multi_args
+                .get_or_insert(preset.supports_multi_args);
+        }
+        if self.install.is_empty() {
+            return Err("missing `install`, or a `preset` providing it".to_string());
+        }
+        Ok(())
+    }
+
+    /// Adds the packages of the manager's `groups` that are among `selected`, returning the
+    /// names of those it has.
+    pub fn apply_groups<'a>(&mut self, selected: &'a [String]) -> Vec<&'a str> {
+        let mut applied = vec![];
+        for name in selected {
+            let Some(group) = self.groups.as_ref().and_then(|g| g.get(name)) else {
+                continue;
+            };
+            extend_packages(&mut self.packages, group.packages.clone());
+            applied.push(name.as_str());
+        }
+        applied
+    }
+
+    /// Applies the manager's overrides for `profile`, returning whether it has any.
+    pub fn apply_profile(&mut self, profile: &str) -> bool {
+        let Some(overrides) = self.profiles.as_ref().and_then(|p| p.get(profile)).cloned() else {
+            return false;
+        };
+        self.apply_overrides(&overrides);
+        true
+    }
+
+    /// Applies the manager's overrides for the machine named `host`, returning whether it has
+    /// any. A fully qualified name also matches overrides for its first label.
+    pub fn apply_host(&mut self, host: &str) -> bool {
+        let short = host.split('.').next().unwrap_or(host);
+        let Some(overrides) = self
+            .hosts
+            .as_ref()
+            .and_then(|h| h.get(host).or_else(|| h.get(short)))
+            .cloned()
+        else {
+            return false;
+        };
+        self.apply_overrides(&overrides);
+        true
+    }
+
+    fn apply_overrides(&mut self, overrides: &Profile) {
+        if overrides.replace.unwrap_or(false) {
+            self.packages.clear();
+        }
+        extend_packages(&mut self.packages, overrides.packages.iter().cloned());
+    }
+
+    /// The package lists of the invocations of `template` for `packages`: batches of at most
+    /// `batch_size`, or one package each without multi args. Pinned packages are passed on
+    /// their own when the template takes `$version`.
+    pub fn invocations(&self, template: &CommandTemplate, packages: &[String]) -> Vec<Vec<String>> {
+        let (pinned, rest): (Vec<String>, Vec<String>) = if template.has_version_placeholder() {
+            packages
+                .iter()
+                .cloned()
+                .partition(|p| Package::parse(p).version.is_some())
+        } else {
+            (vec![], packages.to_vec())
+        };
+        let batch_size =
+            if self.supports_multi_args.unwrap_or(true) && !template.takes_one_package() {
+                self.batch_size.unwrap_or(usize::MAX).max(1)
+            } else {
+                1
+            };
+        rest.chunks(batch_size)
+            .map(<[String]>::to_vec)
+            .chain(pinned.into_iter().map(|p| vec![p]))
+            .collect()
+    }
+
+    /// Whether the manager is enabled and meant for this OS.
+    pub fn is_active(&self) -> bool {
+        self.enabled.unwrap_or(true)
+            && self
+                .os
+                .as_ref()
+                .is_none_or(|os| os.iter().any(|os| os == env::consts::OS))
+    }
+
+    /// Whether a command of this manager exiting with `status` succeeded, counting `ignore_exit_codes`.
+    pub fn accepts(&self, status: ExitStatus) -> bool {
+        status.success()
+            || status.code().is_some_and(|code| {
+                self.ignore_exit_codes
+                    .as_ref()
+                    .is_some_and(|codes| codes.contains(&code))
+            })
+    }
+}
+
+/// Names of the packages declared by more than one of the active managers, with those managers.
+pub fn duplicate_packages(managers: &[Dpm]) -> BTreeMap<String, Vec<String>> {
+    let mut declared: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for m in managers.iter().filter(|m| m.is_active()) {
+        let name = m.name.clone().unwrap_or_default();
+        for p in m.packages.iter().filter(|p| p.is_active()) {
+            let by = declared.entry(p.name.clone()).or_default();
+            if !by.contains(&name) {
+                by.push(name.clone());
+            }
+        }
+    }
+    declared.retain(|_, by| by.len() > 1);
+    declared
+}
+
+/// Orders managers so each comes after the managers in its `after`, otherwise keeping
+/// their order. Names in `after` that aren't among `managers` are ignored.
+pub fn order_by_after(managers: &[Dpm]) -> Result<Vec<&Dpm>> {
+    let mut remaining: Vec<&Dpm> = managers.iter().collect();
+    let mut ordered: Vec<&Dpm> = vec![];
+    while !remaining.is_empty() {
+        let ready = remaining.iter().position(|m| {
+            m.after.iter().flatten().all(|dep| {
+                !remaining
+                    .iter()
+                    .any(|r| r.name.as_deref() == Some(dep.as_str()))
+            })
+        });
+        let Some(i) = ready else {
+            return Err(DpmError::DependencyCycle(
+                remaining.iter().filter_map(|m| m.name.clone()).collect(),
+            ));
+        };
+        ordered.push(remaining.remove(i));
+    }
+    Ok(ordered)
+}
+
+/// Builds a [`Dpm`] in code, for embedding dpmm without manager toml files.
+#[derive(Clone, Debug, Default)]
+pub struct DpmBuilder {
+    dpm: Dpm,
+}
+
+impl DpmBuilder {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.dpm.name = Some(name.into());
+        self
+    }
+
+    /// Only recorded, the built manager's [`Dpm::apply_preset`] fills in its commands.
+    pub fn preset(mut self, preset: impl Into<String>) -> Self {
+        self.dpm.preset = Some(preset.into());
+        self
+    }
+
+    /// Only recorded, loading the manager from its file resolves what it extends.
+    pub fn extends(mut self, base: impl Into<String>) -> Self {
+        self.dpm.extends = Some(base.into());
+        self
+    }
+
+    pub fn scope(mut self, scope: Scope) -> Self {
+        self.dpm.scope = Some(scope);
+        self
+    }
+
+    pub fn install_user(mut self, install_user: impl Into<CommandTemplate>) -> Self {
+        self.dpm.install_user = Some(install_user.into());
+        self
+    }
+
+    pub fn install_system(mut self, install_system: impl Into<CommandTemplate>) -> Self {
+        self.dpm.install_system = Some(install_system.into());
+        self
+    }
+
+    pub fn uninstall_user(mut self, uninstall_user: impl Into<CommandTemplate>) -> Self {
+        self.dpm.uninstall_user = Some(uninstall_user.into());
+        self
+    }
+
+    pub fn uninstall_system(mut self, uninstall_system: impl Into<CommandTemplate>) -> Self {
+        self.dpm.uninstall_system = Some(uninstall_system.into());
+        self
+    }
+
+    pub fn update(mut self, update: impl Into<CommandTemplate>) -> Self {
+        self.dpm.update = Some(update.into());
+        self
+    }
+
+    pub fn upgrade(mut self, upgrade: impl Into<CommandTemplate>) -> Self {
+        self.dpm.upgrade = Some(upgrade.into());
+        self
+    }
+
+    pub fn install(mut self, install: impl Into<CommandTemplate>) -> Self {
+        self.dpm.install = install.into();
+        self
+    }
+
+    pub fn uninstall(mut self, uninstall: impl Into<CommandTemplate>) -> Self {
+        self.dpm.uninstall = Some(uninstall.into());
+        self
+    }
+
+    pub fn pre_install(mut self, pre_install: impl Into<CommandTemplate>) -> Self {
+        self.dpm.pre_install = Some(pre_install.into());
+        self
+    }
+
+    pub fn post_install(mut self, post_install: impl Into<CommandTemplate>) -> Self {
+        self.dpm.post_install = Some(post_install.into());
+        self
+    }
+
+    pub fn pre_uninstall(mut self, pre_uninstall: impl Into<CommandTemplate>) -> Self {
+        self.dpm.pre_uninstall = Some(pre_uninstall.into());
+        self
+    }
+
+    pub fn post_uninstall(mut self, post_uninstall: impl Into<CommandTemplate>) -> Self {
+        self.dpm.post_uninstall = Some(post_uninstall.into());
+        self
+    }
+
+    pub fn supports_multi_args(mut self, supports_multi_args: bool) -> Self {
+        self.dpm.supports_multi_args = Some(supports_multi_args);
+        self
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.dpm.batch_size = Some(batch_size);
+        self
+    }
+
+    pub fn ignore_exit_codes(mut self, codes: impl IntoIterator<Item = i32>) -> Self {
+        self.dpm.ignore_exit_codes = Some(codes.into_iter().collect());
+        self
+    }
+
+    pub fn is_installed(mut self, is_installed: impl Into<String>) -> Self {
+        self.dpm.is_installed = Some(is_installed.into());
+        self
+    }
+
+    pub fn include_in_all(mut self, include_in_all: bool) -> Self {
+        self.dpm.include_in_all = Some(include_in_all);
+        self
+    }
+
+    pub fn confirm_removals(mut self, confirm_removals: bool) -> Self {
+        self.dpm.confirm_removals = Some(confirm_removals);
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.dpm.enabled = Some(enabled);
+        self
+    }
+
+    pub fn after(mut self, after: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.dpm.after = Some(after.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn os(mut self, os: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.dpm.os = Some(os.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn list_installed(mut self, list_installed: impl Into<String>) -> Self {
+        self.dpm.list_installed = Some(list_installed.into());
+        self
+    }
+
+    pub fn list_available(mut self, list_available: impl Into<String>) -> Self {
+        self.dpm.list_available = Some(list_available.into());
+        self
+    }
+
+    pub fn min_version(
+        mut self,
+        version_check: impl Into<String>,
+        min_version: impl Into<String>,
+    ) -> Self {
+        self.dpm.version_check = Some(version_check.into());
+        self.dpm.min_version = Some(min_version.into());
+        self
+    }
+
+    pub fn env_file(mut self, env_file: impl Into<PathBuf>) -> Self {
+        self.dpm.env_file = Some(env_file.into());
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.dpm
+            .env
+            .get_or_insert_with(BTreeMap::new)
+            .insert(key.into(), value.into());
+        self
+    }
+
+    pub fn cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.dpm.cwd = Some(cwd.into());
+        self
+    }
+
+    pub fn packages_cmd(mut self, packages_cmd: impl Into<String>) -> Self {
+        self.dpm.packages_cmd = Some(packages_cmd.into());
+        self
+    }
+
+    pub fn packages<P: Into<Package>>(mut self, packages: impl IntoIterator<Item = P>) -> Self {
+        self.dpm.packages = packages.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn repos<S: Into<String>>(mut self, repos: impl IntoIterator<Item = S>) -> Self {
+        self.dpm.repos = Some(repos.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn add_repo(mut self, add_repo: impl Into<CommandTemplate>) -> Self {
+        self.dpm.add_repo = Some(add_repo.into());
+        self
+    }
+
+    pub fn remove_repo(mut self, remove_repo: impl Into<CommandTemplate>) -> Self {
+        self.dpm.remove_repo = Some(remove_repo.into());
+        self
+    }
+
+    pub fn absent<S: Into<String>>(mut self, absent: impl IntoIterator<Item = S>) -> Self {
+        self.dpm.absent = Some(absent.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn group(mut self, name: impl Into<String>, group: Group) -> Self {
+        self.dpm
+            .groups
+            .get_or_insert_with(BTreeMap::new)
+            .insert(name.into(), group);
+        self
+    }
+
+    pub fn host(mut self, name: impl Into<String>, overrides: Profile) -> Self {
+        self.dpm
+            .hosts
+            .get_or_insert_with(BTreeMap::new)
+            .insert(name.into(), overrides);
+        self
+    }
+
+    pub fn profile(mut self, name: impl Into<String>, profile: Profile) -> Self {
+        self.dpm
+            .profiles
+            .get_or_insert_with(BTreeMap::new)
+            .insert(name.into(), profile);
+        self
+    }
+
+    pub fn build(self) -> Dpm {
+        self.dpm
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Generation {
+    /// Format version the generation is written in, see [`SCHEMA_VERSION`]
+    pub schema_version: Option<u32>,
+    /// RFC 3339 time the generation was written, older generations don't record it
+    pub created_at: Option<String>,
+    /// Version of dpmm that wrote the generation
+    pub dpm_version: Option<String>,
+    /// Digest of the manager configs the generation was produced from, see [`config_hash`]
+    pub config_hash: Option<String>,
+    /// Human readable label given on switch, e.g. "pre-upgrade"
+    pub label: Option<String>,
+    /// Number of the generation this one was switched from, which no-arg rollback returns to
+    pub parent: Option<u32>,
+    pub managers: Vec<Dpm>,
+}
+
+impl Generation {
+    /// A generation of `managers` written now, by this version of dpmm.
+    pub fn new(managers: Vec<Dpm>) -> Self {
+        Generation {
+            schema_version: Some(SCHEMA_VERSION),
+            created_at: Some(chrono::Local::now().to_rfc3339()),
+            dpm_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            managers,
+            ..Default::default()
+        }
+    }
+
+    /// Whether both generations record the same managers with the same packages, in any order.
+    pub fn same_packages(&self, other: &Generation) -> bool {
+        let state = |generation: &Generation| {
+            let mut managers: Vec<(Option<String>, Vec<Package>)> = generation
+                .managers
+                .iter()
+                .map(|m| {
+                    let mut packages = m.packages.clone();
+                    packages.sort();
+                    (m.name.clone(), packages)
+                })
+                .collect();
+            managers.sort();
+            managers
+        };
+        state(self) == state(other)
+    }
+}
+
+/// A stable (FNV-1a) digest of the serialized manager configs, so it can be compared across
+/// dpmm builds and machines.
+pub fn config_hash(managers: &[Dpm]) -> Result<String> {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for manager in managers {
+        for byte in toml::to_string(manager)?.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    Ok(format!("{hash:016x}"))
+}
+
+/// Re-serializes `new` over the toml file at `path`, only touching the keys whose values
+/// changed so the user's comments and formatting survive.
+pub fn edit_toml(path: &Path, new: &impl Serialize) -> Result<String> {
+    let original = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let parse_error = |message: String| DpmError::ParseError {
+        file: path.to_path_buf(),
+        message,
+    };
+    let mut doc: toml_edit::DocumentMut = original
+        .parse()
+        .map_err(|e: toml_edit::TomlError| parse_error(e.to_string()))?;
+    let old_table: toml::Table =
+        toml::from_str(&original).map_err(|e| parse_error(e.to_string()))?;
+    let new_str = toml::to_string(new)?;
+    let new_doc: toml_edit::DocumentMut = new_str.parse().expect("toml serializes to valid toml");
+    let new_table: toml::Table = toml::from_str(&new_str).expect("toml serializes to valid toml");
+    merge_table(
+        doc.as_table_mut(),
+        new_doc.as_table(),
+        Some(&old_table),
+        &new_table,
+    );
+    Ok(doc.to_string())
+}
+
+/// Updates `old` to `new` in place, only touching what changed so the comments and formatting
+/// of the rest survive. `old_value` and `new_value` are the tables' contents, to compare them.
+fn merge_table(
+    old: &mut toml_edit::Table,
+    new: &toml_edit::Table,
+    old_value: Option<&toml::Table>,
+    new_value: &toml::Table,
+) {
+    let stale: Vec<String> = old
+        .iter()
+        .map(|(k, _)| k.to_string())
+        .filter(|k| !new_value.contains_key(k))
+        .collect();
+    for k in stale {
+        old.remove(&k);
+    }
+    for (k, item) in new.iter() {
+        let Some(new_value) = new_value.get(k) else {
+            continue;
+        };
+        let old_value = old_value.and_then(|t| t.get(k));
+        if old_value == Some(new_value) {
+            continue;
+        }
+        match old.get_mut(k) {
+            Some(old) => merge_item(old, item, old_value, new_value),
+            None => {
+                old.insert(k, item.clone());
+            }
+        }
+    }
+}
+
+fn merge_item(
+    old: &mut toml_edit::Item,
+    new: &toml_edit::Item,
+    old_value: Option<&toml::Value>,
+    new_value: &toml::Value,
+) {
+    use toml_edit::Item;
+    match (&mut *old, new, new_value) {
+        (Item::Table(old), Item::Table(new), toml::Value::Table(new_value)) => merge_table(
+            old,
+            new,
+            old_value.and_then(toml::Value::as_table),
+            new_value,
+        ),
+        (Item::ArrayOfTables(old), Item::ArrayOfTables(new), toml::Value::Array(new_values)) => {
+            let old_values = old_value.and_then(toml::Value::as_array);
+            for (i, (table, new_value)) in new.iter().zip(new_values).enumerate() {
+                let Some(new_value) = new_value.as_table() else {
+                    continue;
+                };
+                match old.get_mut(i) {
+                    Some(old) => merge_table(
+                        old,
+                        table,
+                        old_values
+                            .and_then(|v| v.get(i))
+                            .and_then(toml::Value::as_table),
+                        new_value,
+                    ),
+                    None => old.push(table.clone()),
+                }
+            }
+            while old.len() > new.len() {
+                old.remove(old.len() - 1);
+            }
+        }
+        (Item::Value(old), Item::Value(new), _) => {
+            let decor = old.decor().clone();
+            *old = new.clone();
+            *old.decor_mut() = decor;
+        }
+        (old, new, _) => *old = new.clone(),
+    }
+}
+
+/// How commands spawned for a manager are run.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RunOptions {
+    pub dry_run: bool,
+    pub print_commands: bool,
+    /// Prefix each line a command outputs with `[manager]`
+    pub prefix_output: bool,
+    pub verbose: bool,
+    /// Answer yes to confirmation prompts
+    pub assume_yes: bool,
+    /// Keep going after a manager's update/upgrade fails
+    pub continue_on_error: bool,
+    /// Fail on removals for managers without an uninstall command, instead of skipping them
+    pub strict: bool,
+    /// Leave out the notes about managers with nothing to resolve
+    pub quiet: bool,
+    /// Most managers a switch applies at once, 0 and 1 meaning one at a time
+    pub jobs: usize,
+}
+
+/// Start of the generation file names of a series, `generation_`, or `generation_<prefix>_`
+/// for a host prefix, so machines sharing a cache directory keep their own generations. A
+/// profile gets its own series too, `generation_profile-<profile>_` or
+/// `generation_<prefix>_profile-<profile>_`, so switching profiles doesn't remove the other's
+/// packages, and a profile and a host prefix of the same name don't share one.
+pub fn generation_stem(host_prefix: Option<&str>, profile: Option<&str>) -> String {
+    let prefix = host_prefix
+        .map(|prefix| format!("{prefix}_"))
+        .unwrap_or_default();
+    match profile {
+        Some(profile) => format!("generation_{prefix}profile-{profile}_"),
+        None => format!("generation_{prefix}"),
+    }
+}
+
+/// The generation number of a file of the `stem` series, -1 for other files.
+pub fn extract_gen(s: &fs::DirEntry, stem: &str) -> i32 {
+    s.file_name()
+        .to_string_lossy()
+        .strip_prefix(stem)
+        .and_then(|s| s.strip_suffix(".toml"))
+        .and_then(|n| n.parse::<i32>().ok())
+        .unwrap_or(-1)
+}
+
+pub fn generation_files(dir: impl AsRef<Path>, stem: &str) -> Result<Vec<fs::DirEntry>> {
+    // the cache isn't created under --dry-run
+    if !dir.as_ref().exists() {
+        return Ok(vec![]);
+    }
+    let mut paths: Vec<_> = fs::read_dir(dir.as_ref())?
+        .filter_map(std::result::Result::ok)
+        .collect();
+    paths.sort_by_key(|p| extract_gen(p, stem));
+    Ok(paths.into_iter().rev().collect())
+}
+
+/// Numbers of the stored generations, newest first.
+pub fn generation_numbers(dir: impl AsRef<Path>, stem: &str) -> Result<Vec<u32>> {
+    Ok(generation_files(dir, stem)?
+        .iter()
+        .map(|p| extract_gen(p, stem))
+        .filter(|n| *n != -1)
+        .map(|n| n as u32)
+        .collect())
+}
+
+pub fn get_gen_file(dir: impl AsRef<Path>, stem: &str, idx: usize) -> Option<(PathBuf, u32)> {
+    let paths = generation_files(dir.as_ref(), stem).ok()?;
+    let f = paths.get(idx);
+    if let Some(f) = f {
+        let n = extract_gen(f, stem);
+        if n == -1 {
+            None
+        } else {
+            Some((f.path(), n as u32))
+        }
+    } else {
+        None
+    }
+}
+
+/// Matches `name` against a shell-style glob, where `*` matches any run of characters and `?`
+/// any single one.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut pi, mut ni) = (0, 0);
+    // last `*` seen and the name position it was tried at, to backtrack to
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// Names of the managers matching any of `patterns`, erroring on a pattern that matches none.
+pub fn match_managers(managers: &[Dpm], patterns: &[String]) -> Result<HashSet<String>> {
+    let names: Vec<String> = managers.iter().filter_map(|m| m.name.clone()).collect();
+    let mut matched = HashSet::new();
+    for pattern in patterns {
+        let mut any = false;
+        for name in names.iter().filter(|name| glob_match(pattern, name)) {
+            any = true;
+            matched.insert(name.clone());
+        }
+        if !any {
+            return Err(DpmError::NoMatchingManager {
+                pattern: pattern.clone(),
+                available: names,
+            });
+        }
+    }
+    Ok(matched)
+}
+
+/// Reads newline-separated package names, skipping blank lines and `#` comments.
+pub fn read_package_list(reader: impl BufRead) -> std::io::Result<Vec<String>> {
+    let mut packages = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            packages.push(line.to_string());
+        }
+    }
+    Ok(packages)
+}
+
+pub fn diff_unique(old: &[String], new: &[String]) -> (Vec<String>, Vec<String>) {
+    let old_set: HashSet<_> = old.iter().cloned().collect();
+    let new_set: HashSet<_> = new.iter().cloned().collect();
+    let added = new_set.difference(&old_set).cloned().collect();
+    let removed = old_set.difference(&new_set).cloned().collect();
+    (added, removed)
+}
+
+/// Package entries of two lists, matched by name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntriesDiff<T> {
+    pub added: Vec<T>,
+    pub removed: Vec<T>,
+    /// (old, new) entries with the same name but different contents, e.g. another version
+    pub changed: Vec<(T, T)>,
+}
+
+/// Diffs entries by the name `name` gives them, keeping their order in `old` and `new`.
+/// Entries present in both that `same` doesn't find identical are reported as changed rather
+/// than going unnoticed.
+pub fn diff_entries<T: Clone + PartialEq>(
+    old: &[T],
+    new: &[T],
+    name: impl Fn(&T) -> &str,
+    same: impl Fn(&T, &T) -> bool,
+) -> EntriesDiff<T> {
+    let mut diff = EntriesDiff {
+        added: vec![],
+        removed: vec![],
+        changed: vec![],
+    };
+    for n in new {
+        match old.iter().find(|o| name(o) == name(n)) {
+            None if !diff.added.contains(n) => diff.added.push(n.clone()),
+            Some(o) if !same(o, n) && !diff.changed.iter().any(|(_, c)| same(c, n)) => {
+                diff.changed.push((o.clone(), n.clone()))
+            }
+            _ => (),
+        }
+    }
+    for o in old {
+        if !new.iter().any(|n| name(n) == name(o)) && !diff.removed.contains(o) {
+            diff.removed.push(o.clone());
+        }
+    }
+    diff
+}
+
+/// Prefers the time recorded in the generation, falling back to the file's creation time.
+pub fn generation_time(
+    entry: &fs::DirEntry,
+    generation: Option<&Generation>,
+) -> Option<chrono::DateTime<chrono::Local>> {
+    let recorded = generation
+        .and_then(|g| g.created_at.as_ref())
+        .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.with_timezone(&chrono::Local));
+    recorded.or_else(|| Some(entry.metadata().ok()?.created().ok()?.into()))
+}
+
+/// Writes through a temporary file and renames it into place, so readers never see a partial file.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Accepts either a generation name (`generation_3`) or just its number (`3`) in the `stem` series.
+pub fn gen_ref_path(dir: impl AsRef<Path>, stem: &str, generation: &str) -> PathBuf {
+    if generation.parse::<u32>().is_ok() {
+        dir.as_ref().join(format!("{stem}{generation}.toml"))
+    } else {
+        dir.as_ref().join(format!("{generation}.toml"))
+    }
+}
+
+/// The number a generation reference (`generation_3` or `3`) of the `stem` series points at.
+pub fn gen_ref_number(stem: &str, generation: &str) -> Option<u32> {
+    let generation = generation.trim_end_matches(".toml");
+    generation
+        .strip_prefix(stem)
+        .unwrap_or(generation)
+        .parse()
+        .ok()
+}
+
+fn load_toml<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let text = fs::read_to_string(path).map_err(|source| match source.kind() {
+        // left for callers to map to what's missing
+        io::ErrorKind::NotFound => DpmError::Io(source),
+        _ => DpmError::Read {
+            file: path.to_path_buf(),
+            source,
+        },
+    })?;
+    toml::from_str(&text).map_err(|e| DpmError::ParseError {
+        file: path.to_path_buf(),
+        message: e.to_string(),
+    })
+}
+
+/// The config file `<stem>.toml` in `dir`, or `<stem>.json`, `<stem>.yaml` or `<stem>.yml`
+/// when only that exists.
+pub fn config_file(dir: &Path, stem: &str) -> PathBuf {
+    let toml = dir.join(format!("{stem}.toml"));
+    if toml.exists() {
+        return toml;
+    }
+    ["json", "yaml", "yml"]
+        .iter()
+        .map(|ext| dir.join(format!("{stem}.{ext}")))
+        .find(|path| path.exists())
+        .unwrap_or(toml)
+}
+
+/// Loads a config file in the format its extension names.
+fn load_config<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let parse_error = |message: String| DpmError::ParseError {
+        file: path.to_path_buf(),
+        message,
+    };
+    let parse = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => json::parse,
+        Some("yaml" | "yml") => yaml::parse,
+        _ => return load_toml(path),
+    };
+    let text = fs::read_to_string(path).map_err(|source| DpmError::Read {
+        file: path.to_path_buf(),
+        source,
+    })?;
+    parse(&text)
+        .map_err(parse_error)?
+        .try_into()
+        .map_err(|e: toml::de::Error| parse_error(e.to_string()))
+}
+
+/// Loads `dir/dpmm.toml`, or its JSON or YAML form, see [`config_file`].
+pub fn load_dpmm(dir: &Path) -> Result<Dpmm> {
+    let path = config_file(dir, "dpmm");
+    ensure_schema_version(&path)?;
+    match load_config(&path) {
+        Err(DpmError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+            Err(DpmError::ConfigNotFound(path))
+        }
+        res => res,
+    }
+}
+
+/// Loads `dir/<name>.toml`, or its JSON or YAML form, naming the manager after the file.
+pub fn load_manager(dir: &Path, name: &str) -> Result<Dpm> {
+    let mut dpm: Dpm = match load_config(&config_file(dir, name)) {
+        Err(DpmError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(DpmError::MissingManager(name.to_string()));
+        }
+        res => res?,
+    };
+    dpm.name = Some(name.to_string());
+    complete_manager(
+        dir,
+        &config_file(dir, name),
+        &mut dpm,
+        &mut vec![name.to_string()],
+    )?;
+    Ok(dpm)
+}
+
+/// Loads the manager of a dpmm.toml entry, from its file unless it's defined inline.
+pub fn load_entry(dir: &Path, entry: &ManagerEntry) -> Result<Dpm> {
+    let Some(inline) = &entry.inline else {
+        return load_manager(dir, &entry.name);
+    };
+    let mut dpm = Dpm::clone(inline);
+    complete_manager(
+        dir,
+        &config_file(dir, "dpmm"),
+        &mut dpm,
+        &mut vec![entry.name.clone()],
+    )?;
+    Ok(dpm)
+}
+
+/// Applies the base manager, the preset and the includes of a manager loaded from `file`.
+/// `chain` holds the names of the managers extending this one, to catch cycles.
+fn complete_manager(dir: &Path, file: &Path, dpm: &mut Dpm, chain: &mut Vec<String>) -> Result<()> {
+    let parse_error = |message: String| DpmError::ParseError {
+        file: file.to_path_buf(),
+        message,
+    };
+    if let Some(base) = dpm.extends.take() {
+        if chain.contains(&base) {
+            chain.push(base);
+            return Err(parse_error(format!(
+                "managers extend each other: {}",
+                chain.join(" -> ")
+            )));
+        }
+        let base_file = config_file(dir, &base);
+        if base_file.exists() {
+            let mut base_dpm: Dpm = load_config(&base_file)?;
+            chain.push(base);
+            complete_manager(dir, &base_file, &mut base_dpm, chain)?;
+            dpm.inherit(base_dpm);
+        } else if preset::find(&base).is_some() {
+            if dpm.preset.is_some() {
+                return Err(parse_error(
+                    "`extends` a preset and sets a `preset` too".to_string(),
+                ));
+            }
+            dpm.preset = Some(base);
+        } else {
+            return Err(parse_error(format!(
+                "extends {base}, which is neither a manager file nor a preset"
+            )));
+        }
+    }
+    dpm.apply_preset().map_err(parse_error)?;
+    // generations record the merged packages rather than where they came from
+    for path in include_paths(dir, &dpm.include.take().unwrap_or_default())? {
+        let included: Included = load_include(&path)?;
+        extend_packages(&mut dpm.packages, included.packages);
+    }
+    Ok(())
+}
+
+/// A file included by a manager.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Included {
+    packages: Vec<Package>,
+}
+
+/// The config file declaring the package `name` for the manager of `entry`: its own file, or
+/// dpmm.toml for inline managers, or else a file either of them includes. `None` for packages
+/// coming from `packages_cmd`.
+pub fn package_source(
+    dir: &Path,
+    dpmm: &Dpmm,
+    entry: &ManagerEntry,
+    name: &str,
+) -> Result<Option<PathBuf>> {
+    let declares = |packages: &[Package]| packages.iter().any(|p| glob_match(&p.name, name));
+    let (own, dpm) = match &entry.inline {
+        Some(inline) => (config_file(dir, "dpmm"), Dpm::clone(inline)),
+        None => {
+            let own = config_file(dir, &entry.name);
+            let dpm: Dpm = load_config(&own)?;
+            (own, dpm)
+        }
+    };
+    let overrides = dpm.hosts.iter().chain(dpm.profiles.iter()).flatten();
+    if declares(&dpm.packages)
+        || dpm
+            .groups
+            .iter()
+            .flatten()
+            .any(|(_, g)| declares(&g.packages))
+        || overrides.into_iter().any(|(_, p)| declares(&p.packages))
+    {
+        return Ok(Some(own));
+    }
+    for path in include_paths(dir, dpm.include.as_deref().unwrap_or_default())? {
+        let included: Included = load_include(&path)?;
+        if declares(&included.packages) {
+            return Ok(Some(path));
+        }
+    }
+    for path in include_paths(dir, dpmm.include.as_deref().unwrap_or_default())? {
+        let included: BTreeMap<String, Vec<Package>> = load_include(&path)?;
+        if included.get(&entry.name).is_some_and(|list| declares(list)) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// The packages that the files included by dpmm.toml add to each manager.
+pub fn load_included_packages(dir: &Path, dpmm: &Dpmm) -> Result<BTreeMap<String, Vec<Package>>> {
+    let mut packages: BTreeMap<String, Vec<Package>> = BTreeMap::new();
+    for path in include_paths(dir, dpmm.include.as_deref().unwrap_or_default())? {
+        let included: BTreeMap<String, Vec<Package>> = load_include(&path)?;
+        for (name, list) in included {
+            if !dpmm.contains(&name) {
+                return Err(DpmError::ParseError {
+                    file: path,
+                    message: format!("{name} isn't a manager in dpmm.toml"),
+                });
+            }
+            extend_packages(packages.entry(name).or_default(), list);
+        }
+    }
+    Ok(packages)
+}
+
+/// Files matching `patterns`, relative to `dir`. Only file names may contain the wildcards
+/// `*` and `?`, e.g. `work/*.toml`, and their matches are sorted. Other paths must exist.
+pub fn include_paths(dir: &Path, patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for pattern in patterns {
+        let path = dir.join(expand_path(pattern));
+        let file_pattern = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if !file_pattern.contains(['*', '?']) {
+            paths.push(path);
+            continue;
+        }
+        let parent = path.parent().unwrap_or(dir);
+        let entries = fs::read_dir(parent).map_err(|source| DpmError::Read {
+            file: parent.to_path_buf(),
+            source,
+        })?;
+        let mut matched: Vec<PathBuf> = entries
+            .filter_map(std::result::Result::ok)
+            .map(|e| e.path())
+            .filter(|p| {
+                p.is_file()
+                    && p.file_name()
+                        .is_some_and(|n| glob_match(&file_pattern, &n.to_string_lossy()))
+            })
+            .collect();
+        matched.sort();
+        paths.extend(matched);
+    }
+    Ok(paths)
+}
+
+fn load_include<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    load_config(path).map_err(|e| match e {
+        DpmError::Io(source) => DpmError::Read {
+            file: path.to_path_buf(),
+            source,
+        },
+        e => e,
+    })
+}
+
+/// Appends the packages not already in `packages`.
+pub fn extend_packages(packages: &mut Vec<Package>, more: impl IntoIterator<Item = Package>) {
+    for p in more {
+        if !packages.contains(&p) {
+            packages.push(p);
+        }
+    }
+}
+
+pub fn load_generation(path: &Path) -> Result<Generation> {
+    ensure_schema_version(path)?;
+    load_toml(path)
+}
+
+/// Errors for files written in a newer format than this dpmm knows, before their fields can be
+/// misread. Files that don't parse are left for the full load to report.
+fn ensure_schema_version(path: &Path) -> Result<()> {
+    #[derive(Deserialize)]
+    struct Versioned {
+        schema_version: Option<u32>,
+    }
+    if let Ok(Versioned {
+        schema_version: Some(version),
+    }) = load_config(path)
+        && version > SCHEMA_VERSION
+    {
+        return Err(DpmError::ParseError {
+            file: path.to_path_buf(),
+            message: format!(
+                "schema_version {version} is newer than the {SCHEMA_VERSION} this dpmm reads, update dpmm"
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Runs the migrations a toml dpmm.toml needs to reach [`SCHEMA_VERSION`] and stamps it with
+/// that version, keeping comments and formatting. `None` when it's already current.
+pub fn migrate_config(path: &Path) -> Result<Option<String>> {
+    let parse_error = |message: String| DpmError::ParseError {
+        file: path.to_path_buf(),
+        message,
+    };
+    let original = fs::read_to_string(path)?;
+    let mut doc: toml_edit::DocumentMut = original
+        .parse()
+        .map_err(|e: toml_edit::TomlError| parse_error(e.to_string()))?;
+    let version = match doc.get("schema_version") {
+        None => 0,
+        Some(item) => item
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| parse_error("schema_version isn't a version number".to_string()))?,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(parse_error(format!(
+            "schema_version {version} is newer than the {SCHEMA_VERSION} this dpmm reads, update dpmm"
+        )));
+    }
+    for migration in &CONFIG_MIGRATIONS[version as usize..] {
+        migration(&mut doc);
+    }
+    doc["schema_version"] = toml_edit::value(i64::from(SCHEMA_VERSION));
+    let migrated = doc.to_string();
+    Ok((migrated != original).then_some(migrated))
+}
+
+pub fn read_generation(dir: impl AsRef<Path>, stem: &str, generation: &str) -> Result<Generation> {
+    match load_generation(&gen_ref_path(dir, stem, generation)) {
+        Err(DpmError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+            Err(DpmError::GenerationNotFound(generation.to_string()))
+        }
+        res => res,
+    }
+}
+
+/// What switching a manager between two generations changes.
+#[derive(Clone, Debug, Default)]
+pub struct ManagerChanges {
+    pub name: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Removals declined at the confirmation prompt, which are still installed
+    pub declined: Vec<String>,
+    /// Optional packages whose install failed, which aren't recorded
+    pub failed: Vec<String>,
+    /// Entries whose options changed in a way that needs no reinstall, e.g. their reason
+    pub updated: Vec<String>,
+    /// Whether the manager is missing from the old generation
+    pub new: bool,
+    /// Time taken to resolve the changes
+    pub elapsed: Duration,
+}
+
+/// Per manager changes of [`apply_generation`], in the order they're applied.
+#[derive(Clone, Debug, Default)]
+pub struct ChangeSummary {
+    pub managers: Vec<ManagerChanges>,
+}
+
+impl ChangeSummary {
+    /// Whether anything changed and so the new state should be recorded.
+    pub fn changed(&self) -> bool {
+        self.managers.iter().any(|m| {
+            m.new
+                || !m.removed.is_empty()
+                || !m.updated.is_empty()
+                || m.added.iter().any(|a| !m.failed.contains(a))
+        })
+    }
+
+    pub fn removals(&self) -> usize {
+        self.managers.iter().map(|m| m.removed.len()).sum()
+    }
+}
+
+/// Computes the changes [`apply_generation`] would resolve, without running anything.
+/// Managers of `old` missing from `new` are ignored.
+pub fn plan_generation(old: &Generation, new: &Generation) -> Result<ChangeSummary> {
+    let mut managers = vec![];
+    for m in order_by_after(&new.managers)? {
+        let name = m.name.clone().unwrap_or_default();
+        let changes = match old.managers.iter().find(|o| o.name == m.name) {
+            Some(o) => {
+                // a changed entry, e.g. another version or other args, is reinstalled: its old
+                // form uninstalled, its new one installed
+                let diff = diff_entries(
+                    &o.packages,
+                    &m.packages,
+                    |p| p.name.as_str(),
+                    Package::same_entry,
+                );
+                let (mut added, mut removed, mut updated) = (
+                    package_specs(&diff.added),
+                    package_specs(&diff.removed),
+                    vec![],
+                );
+                for (old, new) in diff.changed {
+                    if old.installs_alike(&new) {
+                        updated.push(new.spec());
+                    } else {
+                        removed.push(old.spec());
+                        added.push(new.spec());
+                    }
+                }
+                ManagerChanges {
+                    name,
+                    added,
+                    removed,
+                    updated,
+                    ..Default::default()
+                }
+            }
+            None => ManagerChanges {
+                name,
+                added: package_specs(&m.packages),
+                new: true,
+                ..Default::default()
+            },
+        };
+        managers.push(changes);
+    }
+    Ok(ChangeSummary { managers })
+}
+
+/// Installs and uninstalls what it takes to go from `old` to `new`, in `after` order.
+/// Stops before the next manager once interrupted, returning the changes applied so far.
+/// With `opts.jobs` above 1, managers whose `after` managers are done run concurrently.
+pub fn apply_generation(
+    old: &Generation,
+    new: &Generation,
+    opts: &RunOptions,
+) -> Result<ChangeSummary> {
+    let planned = plan_generation(old, new)?.managers;
+    let manager = |changes: &ManagerChanges| {
+        new.managers
+            .iter()
+            .find(|m| m.name.as_deref() == Some(changes.name.as_str()))
+            .expect("planned from new")
+    };
+    let apply = |m: &Dpm, mut changes: ManagerChanges| {
+        let start = Instant::now();
+        let o = old.managers.iter().find(|o| o.name == m.name);
+        check_version(m, opts)?;
+        add_repos(m, o, opts)?;
+        (changes.declined, changes.failed) =
+            resolve_changes(m, o, &changes.added, &changes.removed, opts)?;
+        remove_absent(m, opts)?;
+        remove_repos(m, o, opts)?;
+        changes.elapsed = start.elapsed();
+        Ok(changes)
+    };
+    if opts.jobs <= 1 {
+        let mut summary = ChangeSummary::default();
+        for changes in planned {
+            if interrupted() {
+                break;
+            }
+            summary.managers.push(apply(manager(&changes), changes)?);
+        }
+        return Ok(summary);
+    }
+    let mut done: Vec<Option<ManagerChanges>> = vec![None; planned.len()];
+    let mut started = vec![false; planned.len()];
+    let mut errors = vec![];
+    thread::scope(|s| {
+        let (tx, rx) = mpsc::channel();
+        let (mut running, mut serial) = (0, false);
+        loop {
+            for (i, changes) in planned.iter().enumerate() {
+                if running >= opts.jobs || serial || !errors.is_empty() || interrupted() {
+                    break;
+                }
+                let m = manager(changes);
+                let ready = !started[i]
+                    && m.after.iter().flatten().all(|dep| {
+                        planned
+                            .iter()
+                            .zip(&done)
+                            .all(|(p, d)| &p.name != dep || d.is_some())
+                    });
+                // prompts would interleave with the other managers' output
+                let interactive = m.confirm_removals.unwrap_or(false)
+                    && !changes.removed.is_empty()
+                    && !opts.assume_yes
+                    && !opts.dry_run;
+                if !ready || (interactive && running > 0) {
+                    continue;
+                }
+                started[i] = true;
+                running += 1;
+                serial = interactive;
+                let (tx, changes) = (tx.clone(), changes.clone());
+                s.spawn(move || {
+                    // the receiver waits for every running manager
+                    let _ = tx.send((i, apply(m, changes)));
+                });
+            }
+            if running == 0 {
+                break;
+            }
+            let (i, res) = rx.recv().expect("a manager is running");
+            running -= 1;
+            serial = false;
+            match res {
+                Ok(changes) => done[i] = Some(changes),
+                Err(e) => errors.push(e),
+            }
+        }
+    });
+    match errors.len() {
+        0 => Ok(ChangeSummary {
+            managers: done.into_iter().flatten().collect(),
+        }),
+        1 => Err(errors.remove(0)),
+        _ => Err(DpmError::ManagersFailed(errors)),
+    }
+}
+
+/// Per manager (name, added, removed), including managers present in only one of the generations.
+pub fn diff_generations(
+    old: &Generation,
+    new: &Generation,
+) -> Vec<(String, Vec<String>, Vec<String>)> {
+    let mut out = vec![];
+    for m in &new.managers {
+        let mname = m.name.clone().unwrap_or_default();
+        let old_pkgs = old
+            .managers
+            .iter()
+            .find(|o| o.name == m.name)
+            .map(|o| package_specs(&o.packages))
+            .unwrap_or_default();
+        let (mut added, mut removed) = diff_unique(&old_pkgs, &package_specs(&m.packages));
+        added.sort();
+        removed.sort();
+        out.push((mname, added, removed));
+    }
+    for o in &old.managers {
+        if !new.managers.iter().any(|m| m.name == o.name) {
+            let mut removed = package_specs(&o.packages);
+            removed.sort();
+            out.push((o.name.clone().unwrap_or_default(), vec![], removed));
+        }
+    }
+    out
+}
+
+/// Quotes an argument for POSIX shells, leaving it bare when that's unambiguous.
+pub fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%^".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+pub fn shell_join(args: &[impl AsRef<str>]) -> String {
+    args.iter()
+        .map(|a| shell_quote(a.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Runs a hook of dpmm.toml, e.g. `pre_switch`, in the config directory `dir`. Its `{manager}`
+/// placeholder is `dpmm`.
+pub fn run_hook(hook: &CommandTemplate, dir: &Path, label: &str, opts: &RunOptions) -> Result<()> {
+    let cmd_n_args = hook.argv("dpmm", &[]);
+    if cmd_n_args.is_empty() {
+        return Err(DpmError::EmptyCommand {
+            manager: "dpmm".to_string(),
+            step: label.to_lowercase(),
+        });
+    }
+    let cmd_str = shell_join(&cmd_n_args);
+    if opts.print_commands {
+        println!("{cmd_str}");
+    }
+    if opts.dry_run {
+        if !opts.print_commands {
+            println!("{label}:\n{cmd_str}");
+        }
+        return Ok(());
+    }
+    let mut cmd = Command::new(&cmd_n_args[0]);
+    cmd.args(&cmd_n_args[1..]).current_dir(dir);
+    let status = spawn_and_wait(&mut cmd, "dpmm", opts)?;
+    if !status.success() {
+        return Err(DpmError::CommandFailed {
+            manager: "dpmm".to_string(),
+            command: cmd_str,
+            status,
+        });
+    }
+    Ok(())
+}
+
+/// Spawns `cmd` and waits for it, re-emitting its output line by line prefixed with
+/// `[manager]` when `opts.prefix_output` is set.
+pub fn spawn_and_wait(cmd: &mut Command, manager: &str, opts: &RunOptions) -> Result<ExitStatus> {
+    let status = if opts.prefix_output {
+        wait_prefixed(cmd, manager)?
+    } else {
+        cmd.spawn()?.wait()?
+    };
+    if killed_by_interrupt(&status) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+    Ok(status)
+}
+
+/// Copies `input` to `output` line by line, each line prefixed with `prefix`.
+fn forward_prefixed(input: impl io::Read, prefix: &str, mut output: impl Write) {
+    for line in io::BufReader::new(input).lines().map_while(io::Result::ok) {
+        if writeln!(output, "{prefix} {line}").is_err() {
+            break;
+        }
+    }
+}
+
+/// Runs `cmd` with its output re-emitted line by line, prefixed with `[manager]`.
+fn wait_prefixed(cmd: &mut Command, manager: &str) -> Result<ExitStatus> {
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let prefix = format!("[{manager}]");
+    let stdout = child.stdout.take().map(|out| {
+        let prefix = prefix.clone();
+        thread::spawn(move || forward_prefixed(out, &prefix, io::stdout()))
+    });
+    let stderr = child
+        .stderr
+        .take()
+        .map(|err| thread::spawn(move || forward_prefixed(err, &prefix, io::stderr())));
+    let status = child.wait()?;
+    for reader in [stdout, stderr].into_iter().flatten() {
+        let _ = reader.join();
+    }
+    Ok(status)
+}
+
+pub fn run_command(
+    manager: &Dpm,
+    template: &CommandTemplate,
+    packages: &[String],
+    label: &str,
+    opts: &RunOptions,
+) -> Result<()> {
+    // an empty package would otherwise be dropped by the split, running e.g. a bare `install`
+    if packages.iter().all(|p| p.trim().is_empty()) {
+        return Ok(());
+    }
+    let cmd_n_args = template.argv(manager.name.as_deref().unwrap_or_default(), packages);
+    if cmd_n_args.is_empty() {
+        return Err(DpmError::EmptyCommand {
+            manager: manager.name.clone().unwrap_or_default(),
+            step: label.to_lowercase(),
+        });
+    }
+    let cmd_str = shell_join(&cmd_n_args);
+    let mut cmd = manager_command(manager, &cmd_n_args);
+    let env = manager_env(manager)?;
+    let shown_env = masked_env(&env);
+    let note = if opts.dry_run {
+        not_found_note(&cmd_n_args[0])
+    } else {
+        ""
+    };
+    if opts.print_commands {
+        println!("{shown_env}{cmd_str}{note}");
+    }
+    if opts.dry_run {
+        if !opts.print_commands {
+            println!("{label}:\n{shown_env}{cmd:?}{note}");
+        }
+    } else {
+        // set only now, so the values never show up in printed commands
+        cmd.envs(env);
+        let status = spawn_and_wait(&mut cmd, manager.name.as_deref().unwrap_or_default(), opts)?;
+        if !manager.accepts(status) {
+            return Err(DpmError::CommandFailed {
+                manager: manager.name.clone().unwrap_or_default(),
+                command: cmd_str,
+                status,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The machine's hostname, which picks the managers' `[hosts.<name>]` overrides.
+pub fn hostname() -> Option<String> {
+    let name = env::var("COMPUTERNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| {
+            let output = Command::new("hostname").output().ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+        })?;
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Expands a leading `~` to `HOME`, and `$VAR` and `${VAR}` to the variable's value, in a path
+/// from a config file or the command line. Unset variables are left as written.
+pub fn expand_path(path: &str) -> PathBuf {
+    let home = || env::var("HOME").ok();
+    let mut path = path.to_string();
+    if (path == "~" || path.starts_with("~/"))
+        && let Some(home) = home()
+    {
+        path.replace_range(..1, &home);
+    }
+    let mut out = String::new();
+    let mut rest = path.as_str();
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        match env::var(name) {
+            Ok(value) if !name.is_empty() => out.push_str(&value),
+            _ => out.push_str(&rest[i..i + 1 + len]),
+        }
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+    PathBuf::from(out)
+}
+
+/// Looks `program` up like a shell would, in `PATH` unless it contains a path separator.
+pub fn find_on_path(program: &str) -> Option<PathBuf> {
+    let candidates = |path: PathBuf| {
+        let mut candidates = vec![path.clone()];
+        if cfg!(windows) {
+            candidates.push(path.with_extension("exe"));
+        }
+        candidates
+    };
+    if Path::new(program).components().count() > 1 {
+        return candidates(PathBuf::from(program))
+            .into_iter()
+            .find(|p| p.is_file());
+    }
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .flat_map(|dir| candidates(dir.join(program)))
+        .find(|p| p.is_file())
+}
+
+/// Annotation for dry-run output when the command's program can't be found.
+pub fn not_found_note(program: &str) -> &'static str {
+    if find_on_path(program).is_some() {
+        ""
+    } else {
+        " (not found on PATH)"
+    }
+}
+
+/// A command running `argv` in the manager's `cwd`, without its environment, which is
+/// only set once the command has been printed, see [`manager_env`].
+pub fn manager_command(manager: &Dpm, argv: &[impl AsRef<OsStr>]) -> Command {
+    let mut cmd = Command::new(&argv[0]);
+    cmd.args(&argv[1..]);
+    if let Some(cwd) = &manager.cwd {
+        cmd.current_dir(cwd);
+    }
+    cmd
+}
+
+/// The variables of the manager's `env`, followed by the `KEY=VALUE` lines of its
+/// `env_file`, if any, skipping blank lines and `#` comments.
+pub fn manager_env(manager: &Dpm) -> Result<Vec<(String, String)>> {
+    let mut env: Vec<(String, String)> = manager
+        .env
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let Some(path) = &manager.env_file else {
+        return Ok(env);
+    };
+    let parse_error = |message: String| DpmError::ParseError {
+        file: path.clone(),
+        message,
+    };
+    let contents = fs::read_to_string(path).map_err(|e| parse_error(e.to_string()))?;
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(parse_error(format!("line {} isn't KEY=VALUE", i + 1)));
+        };
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        env.push((key.trim().to_string(), value.to_string()));
+    }
+    Ok(env)
+}
+
+/// `KEY=*** ` for each variable, to show which are set without printing their values.
+pub fn masked_env(env: &[(String, String)]) -> String {
+    env.iter().map(|(key, _)| format!("{key}=*** ")).collect()
+}
+
+/// Runs the manager's `is_installed` query for a package, quietly.
+pub fn is_installed(manager: &Dpm, query: &str, package: &str) -> Result<bool> {
+    let cmd_n_args = CommandTemplate::from(query).argv(
+        manager.name.as_deref().unwrap_or_default(),
+        &[package.to_string()],
+    );
+    let status = manager_command(manager, &cmd_n_args)
+        .envs(manager_env(manager)?)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    Ok(status.success())
+}
+
+/// Runs a command printing package names, one per line, for `step` of the manager.
+fn list_packages(manager: &Dpm, cmd_str: &str, step: &str) -> Result<Vec<String>> {
+    let name = manager.name.clone().unwrap_or_default();
+    let cmd_n_args: Vec<_> = cmd_str.split_whitespace().collect();
+    if cmd_n_args.is_empty() {
+        return Err(DpmError::EmptyCommand {
+            manager: name,
+            step: step.to_string(),
+        });
+    }
+    let output = manager_command(manager, &cmd_n_args)
+        .envs(manager_env(manager)?)
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(DpmError::CommandFailed {
+            manager: name,
+            command: cmd_str.to_string(),
+            status: output.status,
+        });
+    }
+    Ok(read_package_list(&output.stdout[..])?)
+}
+
+/// Appends the packages listed by the manager's `packages_cmd`, if any, to its `packages`.
+pub fn resolve_packages_cmd(manager: &mut Dpm) -> Result<()> {
+    let Some(cmd_str) = &manager.packages_cmd else {
+        return Ok(());
+    };
+    let listed = list_packages(manager, cmd_str, "packages_cmd")?;
+    extend_packages(
+        &mut manager.packages,
+        listed.iter().map(|p| Package::parse(p)),
+    );
+    Ok(())
+}
+
+/// Whether a package name is a pattern, e.g. `texlive-*`, see [`resolve_patterns`].
+pub fn is_pattern(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// Replaces the manager's wildcard packages, e.g. `texlive-*`, by the packages of its
+/// `list_available` command they match, which keep the pattern's options. Generations record
+/// the matches, so packages that stop matching are removed like dropped ones.
+pub fn resolve_patterns(manager: &mut Dpm) -> Result<()> {
+    let (patterns, mut packages): (Vec<Package>, Vec<Package>) = manager
+        .packages
+        .drain(..)
+        .partition(|p| is_pattern(&p.name));
+    if patterns.is_empty() {
+        manager.packages = packages;
+        return Ok(());
+    }
+    let name = manager.name.clone().unwrap_or_default();
+    let Some(cmd_str) = &manager.list_available else {
+        return Err(DpmError::EmptyCommand {
+            manager: name,
+            step: "list_available".to_string(),
+        });
+    };
+    let available = list_packages(manager, cmd_str, "list_available")?;
+    for pattern in patterns {
+        let matches: Vec<Package> = available
+            .iter()
+            .filter(|a| glob_match(&pattern.name, a))
+            .filter(|a| !packages.iter().any(|p| &&p.name == a))
+            .map(|a| Package {
+                name: a.clone(),
+                ..pattern.clone()
+            })
+            .collect();
+        if matches.is_empty() {
+            eprintln!(
+                "Warning: {} matches none of the packages available to {name}",
+                pattern.name
+            );
+        }
+        packages.extend(matches);
+    }
+    manager.packages = packages;
+    Ok(())
+}
+
+/// The packages the manager's `list_installed` command reports, `None` without one.
+pub fn list_installed(manager: &Dpm) -> Result<Option<Vec<String>>> {
+    manager
+        .list_installed
+        .as_ref()
+        .map(|cmd_str| list_packages(manager, cmd_str, "list_installed"))
+        .transpose()
+}
+
+/// The first version number in a command's output, e.g. `2.4.5` of `apt 2.4.5 (amd64)`.
+pub fn find_version(text: &str) -> Option<&str> {
+    let mut numbers = text
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .map(|word| word.trim_matches('.'))
+        .filter(|word| !word.is_empty());
+    let first = numbers.clone().next()?;
+    Some(numbers.find(|word| word.contains('.')).unwrap_or(first))
+}
+
+/// Compares dotted version numbers component by component, missing ones counting as 0.
+pub fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let parts = |v: &str| -> Vec<u64> { v.split('.').map(|p| p.parse().unwrap_or(0)).collect() };
+    let (a, b) = (parts(a), parts(b));
+    let len = a.len().max(b.len());
+    let at = |v: &[u64], i: usize| v.get(i).copied().unwrap_or(0);
+    (0..len)
+        .map(|i| at(&a, i).cmp(&at(&b, i)))
+        .find(|o| o.is_ne())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+/// Errors when the manager's `version_check` reports a version below its `min_version`, before
+/// any of its commands run. Output without a version number only warns. Like other queries,
+/// it isn't run under dry-run.
+pub fn check_version(manager: &Dpm, opts: &RunOptions) -> Result<()> {
+    let (Some(cmd_str), Some(required)) = (&manager.version_check, &manager.min_version) else {
+        return Ok(());
+    };
+    if opts.dry_run {
+        return Ok(());
+    }
+    let name = manager.name.clone().unwrap_or_default();
+    let cmd_n_args = CommandTemplate::from(cmd_str.as_str()).argv(&name, &[]);
+    if cmd_n_args.is_empty() {
+        return Err(DpmError::EmptyCommand {
+            manager: name,
+            step: "version_check".to_string(),
+        });
+    }
+    let output = manager_command(manager, &cmd_n_args)
+        .envs(manager_env(manager)?)
+        .output()?;
+    if !output.status.success() {
+        return Err(DpmError::CommandFailed {
+            manager: name,
+            command: cmd_str.clone(),
+            status: output.status,
+        });
+    }
+    // some tools print their version to stderr
+    let text = [output.stdout, output.stderr].concat();
+    let text = String::from_utf8_lossy(&text);
+    let Some(found) = find_version(&text) else {
+        eprintln!("Warning: found no version number in the output of {name}'s `{cmd_str}`");
+        return Ok(());
+    };
+    if compare_versions(found, required).is_lt() {
+        return Err(DpmError::OutdatedManager {
+            manager: name,
+            found: found.to_string(),
+            required: required.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Asks a yes/no question, defaulting to no. Without a terminal the answer is read from what's
+/// piped in, and none declines. With `assume_yes` the answer is yes.
+pub fn confirm(question: &str, opts: &RunOptions) -> Result<bool> {
+    if opts.assume_yes {
+        return Ok(true);
+    }
+    ask(question, &mut io::stdin().lock())
+}
+
+fn ask(question: &str, input: &mut impl BufRead) -> Result<bool> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let answer = read_answer(input)?.unwrap_or_default();
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Reads a line answering a prompt, `None` at the end of the input. Unlike `read_line`, which
+/// retries reads interrupted by a signal, it gives up once Ctrl-C is pressed.
+pub fn read_answer(input: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = vec![];
+    loop {
+        let buf = match input.fill_buf() {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted && !interrupted() => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if buf.is_empty() {
+            break;
+        }
+        let (len, done) = match buf.iter().position(|&b| b == b'\n') {
+            Some(i) => (i + 1, true),
+            None => (buf.len(), false),
+        };
+        line.extend_from_slice(&buf[..len]);
+        input.consume(len);
+        if done {
+            break;
+        }
+    }
+    Ok((!line.is_empty()).then(|| String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// The repos of `manager` missing from `old`, and those of `old` it dropped.
+fn repo_changes(manager: &Dpm, old: Option<&Dpm>) -> (Vec<String>, Vec<String>) {
+    let repos = manager.repos.as_deref().unwrap_or_default();
+    let old_repos = old.and_then(|o| o.repos.as_deref()).unwrap_or_default();
+    diff_unique(old_repos, repos)
+}
+
+/// Adds the repos the manager declares that `old`, the manager as it was recorded, doesn't,
+/// before its packages are installed. Each is added on its own, `$` getting its words, e.g.
+/// `flathub https://dl.flathub.org/repo/flathub.flatpakrepo`.
+pub fn add_repos(manager: &Dpm, old: Option<&Dpm>, opts: &RunOptions) -> Result<()> {
+    let (added, _) = repo_changes(manager, old);
+    if added.is_empty() {
+        return Ok(());
+    }
+    let Some(add_repo) = &manager.add_repo else {
+        return Err(DpmError::EmptyCommand {
+            manager: manager.name.clone().unwrap_or_default(),
+            step: "add_repo".to_string(),
+        });
+    };
+    for repo in added {
+        let words: Vec<String> = repo.split_whitespace().map(str::to_string).collect();
+        run_command(manager, add_repo, &words, "Adds repo", opts)?;
+    }
+    Ok(())
+}
+
+/// Removes the repos `old` recorded that the manager no longer declares, once its packages
+/// are uninstalled. Managers without `remove_repo` leave them with a warning.
+pub fn remove_repos(manager: &Dpm, old: Option<&Dpm>, opts: &RunOptions) -> Result<()> {
+    let (_, removed) = repo_changes(manager, old);
+    if removed.is_empty() {
+        return Ok(());
+    }
+    let Some(remove_repo) = &manager.remove_repo else {
+        eprintln!(
+            "Warning: {} has no remove_repo command, leaving {} in place",
+            manager.name.as_deref().unwrap_or_default(),
+            removed.join(", ")
+        );
+        return Ok(());
+    };
+    for repo in removed {
+        let words: Vec<String> = repo.split_whitespace().map(str::to_string).collect();
+        run_command(manager, remove_repo, &words, "Removes repo", opts)?;
+    }
+    Ok(())
+}
+
+/// Uninstalls the manager's `absent` packages that are installed, whatever the generations
+/// record. They're found with its `is_installed` query, or else its `list_installed` command.
+/// Absent packages that are also in `packages` are left alone.
+pub fn remove_absent(manager: &Dpm, opts: &RunOptions) -> Result<()> {
+    let name = manager.name.as_deref().unwrap_or_default();
+    let absent: Vec<String> = manager
+        .absent
+        .iter()
+        .flatten()
+        .filter(|a| !manager.packages.iter().any(|p| &&p.name == a))
+        .cloned()
+        .collect();
+    if absent.is_empty() {
+        return Ok(());
+    }
+    let Some(uninstall) = manager.uninstall_in(manager.scope) else {
+        eprintln!(
+            "Warning: {name} has no uninstall command to remove {}",
+            absent.join(" ")
+        );
+        return Ok(());
+    };
+    // like installs, a dry run doesn't query what's installed
+    let present: Vec<String> = if opts.dry_run {
+        absent
+    } else if let Some(query) = &manager.is_installed {
+        let mut present = vec![];
+        for a in absent {
+            if is_installed(manager, query, &a)? {
+                present.push(a);
+            }
+        }
+        present
+    } else if let Some(installed) = list_installed(manager)? {
+        absent
+            .into_iter()
+            .filter(|a| installed.contains(a))
+            .collect()
+    } else {
+        eprintln!(
+            "Warning: {name} needs is_installed or list_installed to find its absent packages"
+        );
+        return Ok(());
+    };
+    for batch in manager.invocations(uninstall, &present) {
+        if interrupted() {
+            return Ok(());
+        }
+        run_command(manager, uninstall, &batch, "Uninstalls", opts)?;
+    }
+    Ok(())
+}
+
+/// Uninstalls `removed` then installs `added`. Returns the removals the user declined,
+/// which are still installed, and the optional packages that failed to install, which aren't.
+///
+/// The packages are the specs of entries of `manager`, or for removals of `old`, the manager
+/// as it was recorded, and commands get the entries' `installed_as` names.
+pub fn resolve_changes(
+    manager: &Dpm,
+    old: Option<&Dpm>,
+    added: &[String],
+    removed: &[String],
+    opts: &RunOptions,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let name = manager.name.as_deref().unwrap_or_default();
+    if added.is_empty() && removed.is_empty() {
+        if !opts.quiet {
+            println!("Nothing to resolve with {name}!");
+        }
+        return Ok((vec![], vec![]));
+    }
+    let entry = |spec: &str, packages: &[Package]| -> Option<Package> {
+        packages.iter().find(|p| p.spec() == spec).cloned()
+    };
+    let old_packages = old.map_or(&[][..], |o| o.packages.as_slice());
+    let installed_id = |spec: &String| {
+        entry(spec, &manager.packages).map_or_else(|| spec.clone(), |p| p.installed_spec())
+    };
+    let scope_of = |spec: &str, packages: &[Package]| {
+        entry(spec, packages)
+            .and_then(|p| p.scope)
+            .or(manager.scope)
+    };
+    // (spec, id, scope) of each removal, the scope as recorded when it was installed
+    let removals: Vec<(&String, String, Option<Scope>)> = removed
+        .iter()
+        .map(|spec| {
+            let recorded = entry(spec, old_packages).is_some();
+            let packages = if recorded {
+                old_packages
+            } else {
+                &manager.packages
+            };
+            let scope = scope_of(spec, packages);
+            let package = entry(spec, packages).unwrap_or_else(|| Package::parse(spec));
+            // managers spelling versions apart with `$version` uninstall just the names,
+            // whatever version is installed, while for others the version is part of the name,
+            // e.g. brew's gcc@14
+            let id = if manager.install_in(scope).has_version_placeholder() {
+                package.installed_name().to_string()
+            } else {
+                package.installed_spec()
+            };
+            (spec, id, scope)
+        })
+        .collect();
+    let (removals, unremovable): (Vec<_>, Vec<_>) = removals
+        .into_iter()
+        .partition(|(_, _, scope)| manager.uninstall_in(*scope).is_some());
+    let removal_ids: Vec<String> = removals.iter().map(|(_, id, _)| id.to_string()).collect();
+    let (mut declined, mut failed) = (vec![], vec![]);
+    if !unremovable.is_empty() {
+        if opts.strict {
+            return Err(DpmError::EmptyCommand {
+                manager: name.to_string(),
+                step: "uninstalls".to_string(),
+            });
+        }
+        let ids: Vec<&str> = unremovable.iter().map(|(_, id, _)| id.as_str()).collect();
+        // recording them would warn again on every switch, so they're no longer tracked
+        eprintln!(
+            "Warning: {name} has no uninstall command for {}, leaving them installed and untracked",
+            ids.join(" ")
+        );
+    }
+    if !removals.is_empty()
+        && manager.confirm_removals.unwrap_or(false)
+        && !opts.dry_run
+        && !confirm(
+            &format!("{name} will uninstall {}. Proceed?", removal_ids.join(" ")),
+            opts,
+        )?
+    {
+        println!("Keeping {name} packages installed");
+        declined = removals
+            .iter()
+            .map(|(spec, _, _)| spec.to_string())
+            .collect();
+    } else if !removals.is_empty() {
+        if let Some(hook) = &manager.pre_uninstall {
+            run_command(manager, hook, &removal_ids, "Pre-uninstall", opts)?;
+        }
+        // each scope's packages go through its own command
+        for scope in SCOPES {
+            let Some(uninstall) = manager.uninstall_in(scope) else {
+                continue;
+            };
+            let ids: Vec<String> = removals
+                .iter()
+                .filter(|(_, _, s)| *s == scope)
+                .map(|(_, id, _)| id.to_string())
+                .collect();
+            for batch in manager.invocations(uninstall, &ids) {
+                if interrupted() {
+                    return Ok((declined, failed));
+                }
+                run_command(manager, uninstall, &batch, "Uninstalls", opts)?;
+            }
+        }
+        if let Some(hook) = &manager.post_uninstall {
+            run_command(manager, hook, &removal_ids, "Post-uninstall", opts)?;
+        }
+    }
+    let mut added = added.to_vec();
+    if let Some(query) = &manager.is_installed
+        && !opts.dry_run
+    {
+        let mut missing = vec![];
+        for a in added {
+            if is_installed(manager, query, &installed_id(&a))? {
+                println!("Skipping {a}, already installed with {name}");
+            } else {
+                missing.push(a);
+            }
+        }
+        added = missing;
+    }
+    // packages with their own install args, or optional ones, are installed alone
+    let args_of = |spec: &str| entry(spec, &manager.packages).and_then(|p| p.args);
+    let is_optional =
+        |spec: &str| entry(spec, &manager.packages).is_some_and(|p| p.optional == Some(true));
+    let (alone, added): (Vec<String>, Vec<String>) = added
+        .into_iter()
+        .partition(|a| args_of(a).is_some() || is_optional(a));
+    let installs: Vec<String> = added.iter().chain(&alone).map(installed_id).collect();
+    if let Some(hook) = &manager.pre_install
+        && !installs.is_empty()
+    {
+        run_command(manager, hook, &installs, "Pre-install", opts)?;
+    }
+    for scope in SCOPES {
+        let ids: Vec<String> = added
+            .iter()
+            .filter(|a| scope_of(a, &manager.packages) == scope)
+            .map(installed_id)
+            .collect();
+        let install = manager.install_in(scope);
+        for batch in manager.invocations(install, &ids) {
+            if interrupted() {
+                return Ok((declined, failed));
+            }
+            run_command(manager, install, &batch, "Installs", opts)?;
+        }
+    }
+    for package in alone {
+        if interrupted() {
+            return Ok((declined, failed));
+        }
+        let install = manager.install_in(scope_of(&package, &manager.packages));
+        let install = match args_of(&package) {
+            Some(args) => install.with_args(&args),
+            None => install.clone(),
+        };
+        match run_command(
+            manager,
+            &install,
+            &[installed_id(&package)],
+            "Installs",
+            opts,
+        ) {
+            Err(e) if is_optional(&package) => {
+                eprintln!("Warning: optional {package} failed to install with {name}: {e}");
+                failed.push(package);
+            }
+            res => res?,
+        }
+    }
+    if let Some(hook) = &manager.post_install
+        && !installs.is_empty()
+    {
+        run_command(manager, hook, &installs, "Post-install", opts)?;
+    }
+    Ok((declined, failed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    fn manager(name: &str, packages: &[&str]) -> Dpm {
+        Dpm::builder()
+            .name(name)
+            .install("apt install $")
+            .packages(packages.iter().copied())
+            .build()
+    }
+
+    #[test]
+    fn argv_substitutes_pinned_version() {
+        let template = CommandTemplate::from("cargo install $ --version $version");
+        assert_eq!(
+            template.argv("", &strings(&["ripgrep@14.1.0"])),
+            ["cargo", "install", "ripgrep", "--version", "14.1.0"]
+        );
+    }
+
+    #[test]
+    fn argv_leaves_out_unpinned_version() {
+        let requests = strings(&["requests"]);
+        let pip = CommandTemplate::from("pip install $==$version");
+        assert_eq!(pip.argv("", &requests), ["pip", "install", "requests"]);
+        assert_eq!(
+            pip.argv("", &strings(&["requests@2.32.0"])),
+            ["pip", "install", "requests==2.32.0"]
+        );
+        let ripgrep = strings(&["ripgrep"]);
+        for cargo in [
+            "cargo install $ --version $version",
+            "cargo install $ --version=$version",
+        ] {
+            assert_eq!(
+                CommandTemplate::from(cargo).argv("", &ripgrep),
+                ["cargo", "install", "ripgrep"]
+            );
+        }
+        let npm = CommandTemplate::from(strings(&["npm", "i", "-g", "{package}@{version}"]));
+        assert_eq!(
+            npm.argv("", &strings(&["pnpm"])),
+            ["npm", "i", "-g", "pnpm"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn uninstalls_pass_names() {
+        let log = env::temp_dir().join(format!("dpmm-uninstalls-{}", std::process::id()));
+        let script = format!("echo \"$@\" >> {}", log.display());
+        let pip = Dpm::builder()
+            .name("pip")
+            .install("pip install {package}=={version}")
+            .uninstall(strings(&["sh", "-c", &script, "sh", "{packages}"]))
+            .build();
+        let old: Vec<Package> = vec!["requests@2.31.0".into(), "black".into()];
+        let old = Dpm {
+            packages: old,
+            ..pip.clone()
+        };
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        resolve_changes(&pip, Some(&old), &[], &package_specs(&old.packages), &opts).unwrap();
+        let logged = fs::read_to_string(&log).unwrap();
+        fs::remove_file(&log).unwrap();
+        assert_eq!(logged, "requests black\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn uninstalls_use_the_scope_command() {
+        let log = env::temp_dir().join(format!("dpmm-scoped-{}", std::process::id()));
+        let script = format!("echo \"$@\" >> {}", log.display());
+        let pipx = Dpm::builder()
+            .name("pipx")
+            .install("pipx install {package}")
+            .scope(Scope::User)
+            .uninstall_user(strings(&["sh", "-c", &script, "sh", "{packages}"]))
+            .build();
+        let mut system: Package = "httpie".into();
+        system.scope = Some(Scope::System);
+        let old = Dpm {
+            packages: vec!["black".into(), system],
+            ..pipx.clone()
+        };
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        resolve_changes(&pipx, Some(&old), &[], &package_specs(&old.packages), &opts).unwrap();
+        let logged = fs::read_to_string(&log).unwrap();
+        fs::remove_file(&log).unwrap();
+        // httpie has no uninstall_system nor uninstall to go through
+        assert_eq!(logged, "black\n");
+    }
+
+    #[test]
+    fn expand_path_home_and_vars() {
+        let home = env::var("HOME").expect("HOME is set");
+        assert_eq!(expand_path("~/foo"), PathBuf::from(format!("{home}/foo")));
+        assert_eq!(
+            expand_path("${HOME}/bar"),
+            PathBuf::from(format!("{home}/bar"))
+        );
+        assert_eq!(expand_path("/etc/dpmm"), PathBuf::from("/etc/dpmm"));
+        assert_eq!(
+            expand_path("$DPMM_SURELY_UNSET/x"),
+            PathBuf::from("$DPMM_SURELY_UNSET/x")
+        );
+    }
+
+    #[test]
+    fn diff_unique_sets() {
+        let (mut added, removed) = diff_unique(&strings(&["a", "b"]), &strings(&["b", "c", "d"]));
+        added.sort();
+        assert_eq!(added, ["c", "d"]);
+        assert_eq!(removed, ["a"]);
+    }
+
+    #[test]
+    fn diff_generations_covers_both_sides() {
+        let old = Generation {
+            managers: vec![manager("apt", &["git", "fd"]), manager("snap", &["code"])],
+            ..Default::default()
+        };
+        let new = Generation {
+            managers: vec![manager("apt", &["git", "bat"]), manager("npm", &["pnpm"])],
+            ..Default::default()
+        };
+        assert_eq!(
+            diff_generations(&old, &new),
+            [
+                ("apt".to_string(), strings(&["bat"]), strings(&["fd"])),
+                ("npm".to_string(), strings(&["pnpm"]), vec![]),
+                ("snap".to_string(), vec![], strings(&["code"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_entries_reports_changed_versions() {
+        let old: Vec<Package> = vec!["git".into(), "ripgrep@13".into(), "fd".into()];
+        let new: Vec<Package> = vec!["git".into(), "ripgrep@14".into(), "bat".into()];
+        let diff = diff_entries(&old, &new, |p| p.name.as_str(), Package::same_entry);
+        assert_eq!(package_specs(&diff.added), ["bat"]);
+        assert_eq!(package_specs(&diff.removed), ["fd"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].0.spec(), "ripgrep@13");
+        assert_eq!(diff.changed[0].1.spec(), "ripgrep@14");
+    }
+
+    #[test]
+    fn diff_entries_compares_options() {
+        let old: Vec<Package> = vec!["git".into(), "ripgrep".into()];
+        let mut new = old.clone();
+        new[1].args = Some("--locked".into());
+        let diff = diff_entries(&old, &new, |p| p.name.as_str(), Package::same_entry);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].1.args, Some("--locked".into()));
+    }
+
+    #[test]
+    fn plan_generation_reinstalls_changed_entries() {
+        let old = Generation {
+            managers: vec![manager("cargo", &["ripgrep@13", "fd", "bat"])],
+            ..Default::default()
+        };
+        let mut new = Generation {
+            managers: vec![manager("cargo", &["ripgrep@14", "fd", "bat"])],
+            ..Default::default()
+        };
+        new.managers[0].packages[1].args = Some("--locked".into());
+        new.managers[0].packages[2].reason = Some("cat with wings".to_string());
+        let plan = plan_generation(&old, &new).unwrap().managers;
+        let mut added = plan[0].added.clone();
+        added.sort();
+        assert_eq!(added, ["fd", "ripgrep@14"]);
+        let mut removed = plan[0].removed.clone();
+        removed.sort();
+        assert_eq!(removed, ["fd", "ripgrep@13"]);
+        assert_eq!(plan[0].updated, ["bat"]);
+        let cargo = new.managers[0].clone();
+        let install = CommandTemplate::from("cargo install $ --version $version");
+        let batches = cargo.invocations(&install, &plan[0].added);
+        assert!(batches.contains(&strings(&["ripgrep@14"])));
+        assert_eq!(
+            install.argv("cargo", &strings(&["ripgrep@14"])),
+            ["cargo", "install", "ripgrep", "--version", "14"]
+        );
+    }
+
+    #[test]
+    fn generation_stems() {
+        assert_eq!(generation_stem(None, None), "generation_");
+        assert_eq!(generation_stem(Some("laptop"), None), "generation_laptop_");
+        assert_eq!(
+            generation_stem(None, Some("laptop")),
+            "generation_profile-laptop_"
+        );
+        assert_eq!(
+            generation_stem(Some("work"), Some("laptop")),
+            "generation_work_profile-laptop_"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn killed_by_interrupt_status() {
+        let status = |script: &str| Command::new("sh").args(["-c", script]).status().unwrap();
+        assert!(killed_by_interrupt(&status("kill -INT $$")));
+        assert!(!killed_by_interrupt(&status("exit 130")));
+        assert!(!killed_by_interrupt(&status("kill -TERM $$")));
+    }
+
+    #[test]
+    fn read_answer_retries_interrupted_reads() {
+        struct Flaky(bool, io::Cursor<&'static [u8]>);
+        impl io::Read for Flaky {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if std::mem::take(&mut self.0) {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                self.1.read(buf)
+            }
+        }
+        let mut input = io::BufReader::new(Flaky(true, io::Cursor::new(b"y\nn")));
+        assert_eq!(read_answer(&mut input).unwrap().as_deref(), Some("y\n"));
+        assert_eq!(read_answer(&mut input).unwrap().as_deref(), Some("n"));
+        assert_eq!(read_answer(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn generation_time_of_unreadable_files() {
+        let dir = env::temp_dir().join(format!("dpmm-times-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["generation_1.toml", "generation_2.toml"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let mut entries: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap()).collect();
+        entries.sort_by_key(|e| e.file_name());
+        // gone by the time its metadata is read
+        fs::remove_file(entries[0].path()).unwrap();
+        let created_at = "2024-05-01T10:00:00+02:00";
+        let recorded = Generation {
+            created_at: Some(created_at.to_string()),
+            ..Default::default()
+        };
+        let time = generation_time(&entries[1], Some(&recorded));
+        let none = generation_time(&entries[0], None);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(none.is_none());
+        assert_eq!(
+            time.unwrap(),
+            chrono::DateTime::parse_from_rfc3339(created_at).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_join_round_trips() {
+        let argv = strings(&["pip", "install", "My App", "it's", "", "a\"b$c", "x=1"]);
+        let line = shell_join(&argv);
+        let output = Command::new("sh")
+            .args(["-c", &format!("printf '%s\\0' {line}")])
+            .output()
+            .unwrap();
+        let split: Vec<&str> = std::str::from_utf8(&output.stdout)
+            .unwrap()
+            .split_terminator('\0')
+            .collect();
+        assert_eq!(split, argv);
+    }
+
+    #[test]
+    fn builder_sets_fields() {
+        let apt = Dpm::builder()
+            .name("apt")
+            .install("apt install $")
+            .uninstall("apt remove $")
+            .packages(["git", "ripgrep@14"])
+            .build();
+        assert_eq!(apt.name.as_deref(), Some("apt"));
+        assert_eq!(apt.uninstall, Some(CommandTemplate::from("apt remove $")));
+        assert_eq!(package_specs(&apt.packages), ["git", "ripgrep@14"]);
+        let bare = Dpm::default();
+        assert!(bare.packages.is_empty() && bare.uninstall.is_none());
+    }
+
+    #[test]
+    fn new_generations_record_the_version() {
+        let written = toml::to_string(&Generation::new(vec![manager("apt", &["git"])])).unwrap();
+        let read: Generation = toml::from_str(&written).unwrap();
+        assert_eq!(read.dpm_version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+        assert_eq!(read.schema_version, Some(SCHEMA_VERSION));
+        assert!(read.created_at.is_some());
+    }
+
+    #[test]
+    fn current_generation_serialization() {
+        let dir = env::temp_dir().join(format!("dpmm-current-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("dpmm.toml"), "managers = [\"apt\"]\n").unwrap();
+        fs::write(
+            dir.join("apt.toml"),
+            "install = \"apt install $\"\npackages = [\"git\", \"fd@9\"]\n",
+        )
+        .unwrap();
+        let dpmm = load_dpmm(&dir).unwrap();
+        let managers = dpmm
+            .managers
+            .iter()
+            .map(|entry| load_entry(&dir, entry).unwrap())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        let current = Generation {
+            managers,
+            ..Default::default()
+        };
+        assert_eq!(
+            toml::to_string(&current).unwrap(),
+            "[[managers]]\nname = \"apt\"\ninstall = \"apt install $\"\npackages = [\"git\", \"fd@9\"]\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn is_installed_skips_present_packages() {
+        let log = env::temp_dir().join(format!("dpmm-present-{}", std::process::id()));
+        let script = format!("echo \"$@\" >> {}", log.display());
+        let apt = Dpm::builder()
+            .name("apt")
+            .install(strings(&["sh", "-c", &script, "sh", "{packages}"]))
+            .is_installed("test $ = git")
+            .packages(["git", "fd"])
+            .build();
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        resolve_changes(&apt, None, &strings(&["git", "fd"]), &[], &opts).unwrap();
+        let logged = fs::read_to_string(&log).unwrap();
+        fs::remove_file(&log).unwrap();
+        assert_eq!(logged, "fd\n");
+    }
+
+    #[test]
+    fn argv_skips_empty_packages() {
+        let template = CommandTemplate::from("pkg install=$");
+        assert_eq!(template.argv("", &strings(&[" "])), ["pkg", "install="]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn empty_packages_run_nothing() {
+        let log = env::temp_dir().join(format!("dpmm-empty-{}", std::process::id()));
+        let script = format!("echo \"$@\" >> {}", log.display());
+        let mut apt = Dpm::builder()
+            .name("apt")
+            .install(strings(&["sh", "-c", &script, "sh", "{packages}"]))
+            .build();
+        apt.supports_multi_args = Some(false);
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        resolve_changes(&apt, None, &[], &[], &opts).unwrap();
+        resolve_changes(&apt, None, &strings(&[""]), &[], &opts).unwrap();
+        assert!(!log.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn errors_by_failure_mode() {
+        let dir = env::temp_dir().join(format!("dpmm-errors-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let not_found = load_dpmm(&dir);
+        fs::write(dir.join("dpmm.toml"), "managers = [\"apt\", \"npm\"]\n").unwrap();
+        fs::write(dir.join("npm.toml"), "install = [\n").unwrap();
+        let missing = load_manager(&dir, "apt");
+        let parse = load_manager(&dir, "npm");
+        let generation = read_generation(&dir, "generation_", "7");
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            matches!(not_found, Err(DpmError::ConfigNotFound(p)) if p == dir.join("dpmm.toml"))
+        );
+        assert!(matches!(missing, Err(DpmError::MissingManager(name)) if name == "apt"));
+        assert!(
+            matches!(parse, Err(DpmError::ParseError { file, .. }) if file == dir.join("npm.toml"))
+        );
+        assert!(matches!(generation, Err(DpmError::GenerationNotFound(g)) if g == "7"));
+        let failing = Dpm::builder().name("apt").install("false $").build();
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let failed = run_command(
+            &failing,
+            &failing.install,
+            &strings(&["git"]),
+            "Installs",
+            &opts,
+        );
+        assert!(matches!(
+            failed,
+            Err(DpmError::CommandFailed { manager, status, .. })
+                if manager == "apt" && status.code() == Some(1)
+        ));
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("cargo-*", "cargo-stable"));
+        assert!(glob_match("cargo-*", "cargo-nightly"));
+        assert!(!glob_match("cargo-*", "apt"));
+        assert!(glob_match("b?ew", "brew"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("a*b", "acd"));
+    }
+
+    #[test]
+    fn match_managers_by_pattern() {
+        let managers = [
+            manager("cargo-stable", &[]),
+            manager("cargo-nightly", &[]),
+            manager("apt", &[]),
+        ];
+        let mut matched: Vec<_> = match_managers(&managers, &strings(&["cargo-*"]))
+            .unwrap()
+            .into_iter()
+            .collect();
+        matched.sort();
+        assert_eq!(matched, ["cargo-nightly", "cargo-stable"]);
+        assert!(matches!(
+            match_managers(&managers, &strings(&["np?"])),
+            Err(DpmError::NoMatchingManager { pattern, .. }) if pattern == "np?"
+        ));
+    }
+
+    #[test]
+    fn argv_array_keeps_spaces() {
+        let packages = strings(&["My App"]);
+        let line = CommandTemplate::from("flatpak install $");
+        let argv = CommandTemplate::from(strings(&["flatpak", "install", "$"]));
+        assert_eq!(
+            line.argv("", &packages),
+            ["flatpak", "install", "My", "App"]
+        );
+        assert_eq!(argv.argv("", &packages), ["flatpak", "install", "My App"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_lines_get_the_manager_prefix() {
+        let mut child = Command::new("sh")
+            .args(["-c", "echo one; echo; echo two"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut output = vec![];
+        forward_prefixed(child.stdout.take().unwrap(), "[apt]", &mut output);
+        child.wait().unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[apt] one\n[apt] \n[apt] two\n"
+        );
+    }
+
+    #[test]
+    fn piped_answers_confirm() {
+        let answer = |input: &'static str| ask("Proceed?", &mut io::Cursor::new(input)).unwrap();
+        assert!(answer("y\n"));
+        assert!(answer("yes"));
+        assert!(!answer("n\n"));
+        assert!(!answer("\n"));
+        // nothing piped in
+        assert!(!answer(""));
+    }
+
+    #[test]
+    fn loaders_read_or_point_at_the_file() {
+        let dir = env::temp_dir().join(format!("dpmm-loaders-{}", std::process::id()));
+        let bad = dir.join("bad");
+        fs::create_dir_all(&bad).unwrap();
+        fs::write(dir.join("dpmm.toml"), "managers = [\"apt\"]\n").unwrap();
+        fs::write(
+            dir.join("apt.toml"),
+            "install = \"apt install $\"\npackages = [\"git\"]\n",
+        )
+        .unwrap();
+        let generation = dir.join("generation_1.toml");
+        fs::write(&generation, "parent = 0\nmanagers = []\n").unwrap();
+        fs::write(bad.join("dpmm.toml"), "managers = \"apt\"\n").unwrap();
+        fs::write(bad.join("apt.toml"), "packages = [\"git\"]\n").unwrap();
+        let bad_generation = bad.join("generation_1.toml");
+        fs::write(&bad_generation, "parent = \"zero\"\nmanagers = []\n").unwrap();
+        let dpmm = load_dpmm(&dir);
+        let apt = load_manager(&dir, "apt");
+        let read = load_generation(&generation);
+        let bad_dpmm = load_dpmm(&bad);
+        let bad_apt = load_manager(&bad, "apt");
+        let bad_read = load_generation(&bad_generation);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(dpmm.unwrap().names().collect::<Vec<_>>(), ["apt"]);
+        assert_eq!(package_specs(&apt.unwrap().packages), ["git"]);
+        assert_eq!(read.unwrap().parent, Some(0));
+        for (result, file) in [
+            (bad_dpmm.map(|_| ()), bad.join("dpmm.toml")),
+            (bad_apt.map(|_| ()), bad.join("apt.toml")),
+            (bad_read.map(|_| ()), bad_generation),
+        ] {
+            assert!(matches!(result, Err(DpmError::ParseError { file: f, .. }) if f == file));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn packages_cmd_packages_are_diffed() {
+        let mut cargo = manager("cargo", &["git"]);
+        cargo.packages_cmd = Some("printf %s\\n ripgrep fd".to_string());
+        resolve_packages_cmd(&mut cargo).unwrap();
+        let old = Generation {
+            managers: vec![manager("cargo", &["git"])],
+            ..Default::default()
+        };
+        let new = Generation {
+            managers: vec![cargo],
+            ..Default::default()
+        };
+        let plan = plan_generation(&old, &new).unwrap().managers;
+        let mut added = plan[0].added.clone();
+        added.sort();
+        assert_eq!(added, ["fd", "ripgrep"]);
+        assert!(plan[0].removed.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dry_run_notes_missing_programs() {
+        assert_eq!(not_found_note("dpmm-brewe"), " (not found on PATH)");
+        assert_eq!(not_found_note("sh"), "");
+        let brewe = Dpm::builder()
+            .name("brew")
+            .install("dpmm-brewe install $")
+            .build();
+        let opts = RunOptions {
+            dry_run: true,
+            quiet: true,
+            ..Default::default()
+        };
+        // only printed, so the missing program doesn't fail it
+        run_command(
+            &brewe,
+            &brewe.install,
+            &strings(&["git"]),
+            "Installs",
+            &opts,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn invocations_chunk_by_batch_size() {
+        let mut apt = manager("apt", &[]);
+        apt.batch_size = Some(2);
+        let packages = strings(&["a", "b", "c", "d", "e"]);
+        assert_eq!(
+            apt.invocations(&apt.install, &packages),
+            [strings(&["a", "b"]), strings(&["c", "d"]), strings(&["e"])]
+        );
+    }
+
+    #[test]
+    fn plan_generation_with_added_manager() {
+        let old = Generation {
+            managers: vec![manager("apt", &["git", "fd"])],
+            ..Default::default()
+        };
+        let new = Generation {
+            managers: vec![manager("apt", &["git", "bat"]), manager("npm", &["pnpm"])],
+            ..Default::default()
+        };
+        let plan = plan_generation(&old, &new).unwrap().managers;
+        assert_eq!(plan.len(), 2);
+        let apt = plan.iter().find(|m| m.name == "apt").unwrap();
+        assert_eq!(
+            (apt.added.as_slice(), apt.removed.as_slice()),
+            (&strings(&["bat"])[..], &strings(&["fd"])[..])
+        );
+        assert!(!apt.new);
+        let npm = plan.iter().find(|m| m.name == "npm").unwrap();
+        assert_eq!(npm.added, ["pnpm"]);
+        assert!(npm.new);
+    }
+
+    #[test]
+    fn apply_generation_dry_run_summarizes_changes() {
+        let old = Generation {
+            managers: vec![manager("apt", &["git", "fd"])],
+            ..Default::default()
+        };
+        let new = Generation {
+            managers: vec![manager("apt", &["git", "bat"])],
+            ..Default::default()
+        };
+        let opts = RunOptions {
+            quiet: true,
+            dry_run: true,
+            ..Default::default()
+        };
+        let summary = apply_generation(&old, &new, &opts).unwrap();
+        let apt = &summary.managers[0];
+        assert_eq!(
+            (apt.added.as_slice(), apt.removed.as_slice()),
+            (&strings(&["bat"])[..], &strings(&["fd"])[..])
+        );
+        assert!(summary.changed());
+        let unchanged = apply_generation(&new, &new, &opts).unwrap();
+        assert!(!unchanged.changed());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removals_without_uninstall_are_skipped() {
+        let log = env::temp_dir().join(format!("dpmm-no-uninstall-{}", std::process::id()));
+        let script = format!("echo \"$@\" >> {}", log.display());
+        let fetch = Dpm::builder()
+            .name("fetch")
+            .install(strings(&["sh", "-c", &script, "sh", "{packages}"]))
+            .packages(["bat"])
+            .build();
+        let old = Dpm {
+            packages: vec!["fd".into()],
+            ..fetch.clone()
+        };
+        let run = |strict| {
+            let opts = RunOptions {
+                quiet: true,
+                strict,
+                ..Default::default()
+            };
+            let result = resolve_changes(
+                &fetch,
+                Some(&old),
+                &strings(&["bat"]),
+                &strings(&["fd"]),
+                &opts,
+            );
+            let logged = fs::read_to_string(&log).unwrap_or_default();
+            let _ = fs::remove_file(&log);
+            (result, logged)
+        };
+        let (result, logged) = run(false);
+        result.unwrap();
+        assert_eq!(logged, "bat\n");
+        let (result, logged) = run(true);
+        assert!(matches!(result, Err(DpmError::EmptyCommand { .. })));
+        assert_eq!(logged, "");
+    }
+
+    #[test]
+    fn apt_preset_pins_with_equals() {
+        let apt = CommandTemplate::from(preset::find("apt").unwrap().install);
+        assert_eq!(
+            apt.argv("apt", &strings(&["git@1:2.43.0"])),
+            ["sudo", "apt-get", "install", "-y", "git=1:2.43.0"]
+        );
+        assert_eq!(
+            apt.argv("apt", &strings(&["git", "fd"])),
+            ["sudo", "apt-get", "install", "-y", "git", "fd"]
+        );
+    }
+
+    #[test]
+    fn profiles_extend_or_replace_packages() {
+        let apt: Dpm = toml::from_str(
+            r#"
+            install = "apt install $"
+            packages = ["jq", "vim"]
+
+            [profiles.server]
+            packages = ["nginx"]
+
+            [profiles.minimal]
+            replace = true
+            packages = ["vim"]
+            "#,
+        )
+        .unwrap();
+        let with = |profile: &str| {
+            let mut apt = apt.clone();
+            let found = apt.apply_profile(profile);
+            (found, package_specs(&apt.packages))
+        };
+        assert_eq!(package_specs(&apt.packages), ["jq", "vim"]);
+        assert_eq!(with("server"), (true, strings(&["jq", "vim", "nginx"])));
+        assert_eq!(with("minimal"), (true, strings(&["vim"])));
+        assert_eq!(with("laptop"), (false, strings(&["jq", "vim"])));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn env_file_reaches_commands_masked() {
+        let dir = env::temp_dir().join(format!("dpmm-env-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (env_file, log) = (dir.join("secrets.env"), dir.join("log"));
+        fs::write(
+            &env_file,
+            "# token for the private registry\nTOKEN=s3cret\n",
+        )
+        .unwrap();
+        let script = format!("echo \"$TOKEN $@\" >> {}", log.display());
+        let npm = Dpm::builder()
+            .name("npm")
+            .install(strings(&["sh", "-c", &script, "sh", "{packages}"]))
+            .env_file(&env_file)
+            .build();
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let env = manager_env(&npm);
+        let result = resolve_changes(&npm, None, &strings(&["pnpm"]), &[], &opts);
+        let logged = fs::read_to_string(&log);
+        fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+        assert_eq!(logged.unwrap(), "s3cret pnpm\n");
+        assert_eq!(masked_env(&env.unwrap()), "TOKEN=*** ");
+    }
+
+    #[test]
+    fn missing_config_suggests_init() {
+        let dir = env::temp_dir().join(format!("dpmm-no-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let not_found = load_dpmm(&dir).map(|_| ());
+        // a directory fails to read even as root, unlike a file without read permission
+        fs::create_dir(dir.join("dpmm.toml")).unwrap();
+        let unreadable = load_dpmm(&dir).map(|_| ());
+        fs::remove_dir_all(&dir).unwrap();
+        let config = dir.join("dpmm.toml");
+        assert_eq!(
+            not_found.unwrap_err().to_string(),
+            format!(
+                "No dpmm.toml found at {}, run `dpmm init` to create one",
+                config.display()
+            )
+        );
+        let unreadable = unreadable.unwrap_err();
+        assert_eq!(
+            unreadable.to_string(),
+            format!("Failed to read {}", config.display())
+        );
+        assert!(matches!(unreadable, DpmError::Read { .. }));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn jobs_run_independent_managers_concurrently() {
+        let dir = env::temp_dir().join(format!("dpmm-jobs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("log");
+        // each of a and b waits up to 5s for the other to have started
+        let waiting = |name: &str, other: &str| {
+            let (mine, theirs) = (dir.join(name), dir.join(other));
+            let script = format!(
+                "touch {mine}; i=0; while [ ! -e {theirs} ] && [ $i -lt 50 ]; do sleep 0.1; i=$((i+1)); done; \
+                 [ -e {theirs} ] && echo '{name} saw {other}' >> {log}; echo {name} >> {log}",
+                mine = mine.display(),
+                theirs = theirs.display(),
+                log = log.display()
+            );
+            Dpm::builder()
+                .name(name)
+                .install(strings(&["sh", "-c", &script, "sh", "{packages}"]))
+                .packages(["x"])
+        };
+        let logging = format!("echo c >> {}", log.display());
+        let new = Generation {
+            managers: vec![
+                waiting("a", "b").build(),
+                Dpm::builder()
+                    .name("c")
+                    .install(strings(&["sh", "-c", &logging, "sh", "{packages}"]))
+                    .packages(["x"])
+                    .after(["a"])
+                    .build(),
+                waiting("b", "a").build(),
+            ],
+            ..Default::default()
+        };
+        let opts = RunOptions {
+            quiet: true,
+            jobs: 2,
+            ..Default::default()
+        };
+        let result = apply_generation(&Generation::default(), &new, &opts);
+        let logged = fs::read_to_string(&log);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result.unwrap().managers.len(), 3);
+        let logged = logged.unwrap();
+        let lines: Vec<&str> = logged.lines().collect();
+        assert!(
+            lines.contains(&"a saw b") && lines.contains(&"b saw a"),
+            "{lines:?}"
+        );
+        let at = |line| lines.iter().position(|l| *l == line).unwrap();
+        assert!(at("a") < at("c"), "{lines:?}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unversioned_templates_keep_at_in_names() {
+        let log = env::temp_dir().join(format!("dpmm-brew-at-{}", std::process::id()));
+        let script = format!("echo \"$0 $@\" >> {}", log.display());
+        let logging = |step: &str| strings(&["sh", "-c", &script, step, "{packages}"]);
+        let brew = Dpm::builder()
+            .name("brew")
+            .install(logging("install"))
+            .uninstall(logging("uninstall"))
+            .packages(["gcc@14"])
+            .build();
+        let old = Dpm {
+            packages: vec!["gcc@13".into()],
+            ..brew.clone()
+        };
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let result = resolve_changes(
+            &brew,
+            Some(&old),
+            &strings(&["gcc@14"]),
+            &strings(&["gcc@13"]),
+            &opts,
+        );
+        let logged = fs::read_to_string(&log);
+        let _ = fs::remove_file(&log);
+        result.unwrap();
+        assert_eq!(logged.unwrap(), "uninstall gcc@13\ninstall gcc@14\n");
+    }
+
+    #[test]
+    fn argv_substitutes_packages() {
+        let line = CommandTemplate::from("apt install -y $");
+        assert_eq!(
+            line.argv("apt", &strings(&["git", "curl"])),
+            ["apt", "install", "-y", "git", "curl"]
+        );
+        let named = CommandTemplate::from("{manager} add {packages}");
+        assert_eq!(named.argv("npm", &strings(&["a"])), ["npm", "add", "a"]);
+        // dollar signs are left alone by named placeholders, and braces can be escaped
+        let dollars = CommandTemplate::from(strings(&[
+            "sh",
+            "-c",
+            "echo $1 {{packages}}",
+            "sh",
+            "{package}",
+        ]));
+        assert_eq!(
+            dollars.argv("npm", &strings(&["a"])),
+            ["sh", "-c", "echo $1 {packages}", "sh", "a"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn apply_generation_follows_after() {
+        let log = env::temp_dir().join(format!("dpmm-after-{}", std::process::id()));
+        let script = format!("echo \"$0\" >> {}", log.display());
+        let logging = |name: &str| {
+            Dpm::builder()
+                .name(name)
+                .install(strings(&["sh", "-c", &script, "{manager}", "{packages}"]))
+                .packages(["x"])
+        };
+        // listed with each manager before the one it comes after
+        let new = Generation {
+            managers: vec![
+                logging("cargo").after(["rustup"]).build(),
+                logging("flatpak").after(["flathub"]).build(),
+                logging("rustup").build(),
+                logging("flathub").build(),
+            ],
+            ..Default::default()
+        };
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let result = apply_generation(&Generation::default(), &new, &opts);
+        let logged = fs::read_to_string(&log);
+        let _ = fs::remove_file(&log);
+        let order: Vec<String> = result
+            .unwrap()
+            .managers
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        let logged = logged.unwrap();
+        assert_eq!(logged.lines().collect::<Vec<_>>(), order);
+        let at = |name: &str| order.iter().position(|n| n == name).unwrap();
+        assert!(
+            at("rustup") < at("cargo") && at("flathub") < at("flatpak"),
+            "{order:?}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mixed_package_entries_reach_commands() {
+        let log = env::temp_dir().join(format!("dpmm-mixed-{}", std::process::id()));
+        let script = format!("echo \"$0 $@\" >> {}", log.display());
+        let pip: Dpm = toml::from_str(&format!(
+            r#"
+            name = "pip"
+            install = ["sh", "-c", {script:?}, "install", "{{package}}=={{version}}"]
+            uninstall = ["sh", "-c", {script:?}, "uninstall", "{{packages}}"]
+            packages = ["a", {{ name = "b", version = "1" }}]
+            "#
+        ))
+        .unwrap();
+        assert_eq!(package_specs(&pip.packages), ["a", "b@1"]);
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let installed = resolve_changes(&pip, None, &package_specs(&pip.packages), &[], &opts);
+        let old = Dpm {
+            packages: pip.packages.clone(),
+            ..pip.clone()
+        };
+        let removed = Dpm {
+            packages: vec![],
+            ..pip.clone()
+        };
+        let uninstalled = resolve_changes(
+            &removed,
+            Some(&old),
+            &[],
+            &package_specs(&old.packages),
+            &opts,
+        );
+        let logged = fs::read_to_string(&log);
+        let _ = fs::remove_file(&log);
+        installed.unwrap();
+        uninstalled.unwrap();
+        assert_eq!(logged.unwrap(), "install a\ninstall b==1\nuninstall a b\n");
+    }
+}
@@ -1,11 +1,14 @@
 use anyhow::Context;
-use clap::{Parser, Subcommand};
-use serde::{Deserialize, Serialize};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use dpmm::*;
 use std::{
-    collections::HashSet,
-    env, fs,
+    collections::{BTreeMap, HashSet},
+    env,
+    ffi::OsStr,
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
     path::{Path, PathBuf},
-    process::Command,
+    time::{Duration, Instant},
 };
 
 #[allow(dead_code)]
@@ -26,32 +29,71 @@ use windows::*;
 #[cfg(not(windows))]
 use unix::*;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-struct Dpmm {
-    managers: Vec<String>,
-}
-
-#[derive(Clone, Debug, Deserialize, Serialize)]
-struct Dpm {
-    name: Option<String>,
-    update: Option<String>,
-    upgrade: Option<String>,
-    install: String,
-    uninstall: String,
-    supports_multi_args: Option<bool>,
-    packages: Vec<String>,
-}
-
-#[derive(Clone, Debug, Deserialize, Serialize)]
-struct Generation {
-    managers: Vec<Dpm>,
-}
-
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
     #[arg(short, long)]
     dry_run: bool,
+    /// Print notes about skipped steps
+    #[arg(short, long)]
+    verbose: bool,
+    /// Use the managers' [groups.<NAME>] packages, instead of the groups in dpmm.toml
+    #[arg(long = "group", value_name = "NAME")]
+    groups: Vec<String>,
+    /// Apply the managers' [hosts.<NAME>] package overrides instead of those for this machine's hostname
+    #[arg(long, value_name = "NAME")]
+    host: Option<String>,
+    /// Use the [profiles.<NAME>] of dpmm.toml and the managers, with generations of its own
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+    /// Recreate a missing generation_0 baseline
+    #[arg(long)]
+    repair: bool,
+    /// Keep this machine's generations in their own generation_<PREFIX>_N series, e.g. the hostname
+    #[arg(long, value_name = "PREFIX")]
+    host_prefix: Option<String>,
+    /// Keep updating/upgrading the other managers when one fails, then report which failed
+    #[arg(long)]
+    continue_on_error: bool,
+    /// Fail on removals for managers without an uninstall command, instead of skipping them
+    #[arg(long)]
+    strict: bool,
+    /// Apply up to this many managers at once during a switch, each after its `after` managers
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+    /// Print how long each manager's commands took
+    #[arg(long)]
+    time: bool,
+    /// Don't ask for confirmation, e.g. before uninstalls of managers with confirm_removals
+    #[arg(short, long)]
+    yes: bool,
+    /// Print every command as a copy-pasteable shell line before running it
+    #[arg(long)]
+    print_commands: bool,
+    /// Prefix each line of command output with the name of the manager running it
+    #[arg(long)]
+    prefix_output: bool,
+    /// Ignore any project-local .dpm/dpmm.toml and use the global config
+    #[arg(long)]
+    global: bool,
+    /// Use this config directory, overriding DPM_CONFIG_DIR and any project-local config
+    #[arg(long, value_name = "DIR")]
+    config: Option<PathBuf>,
+    /// Seed generation_0 from a generation-shaped file of already installed packages
+    #[arg(long, value_name = "FILE")]
+    assume_installed: Option<PathBuf>,
+    /// Only operate on managers matching these glob patterns
+    #[arg(long, value_name = "PATTERN")]
+    only: Vec<String>,
+    /// Skip managers matching these glob patterns
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+    /// When to color the output
+    #[arg(long, value_enum, default_value_t)]
+    color: ColorChoice,
+    /// Output format of history and of switch --diff-only-on-change summaries
+    #[arg(long, value_enum, default_value_t, global = true)]
+    format: OutputFormat,
     #[command(subcommand)]
     command: Commands,
 }
@@ -59,320 +101,3258 @@ struct Args {
 #[derive(Debug, Subcommand)]
 enum Commands {
     /// Switch to the new configuration
-    Switch,
+    Switch {
+        /// Optional: Label for the generation this switch creates
+        #[arg(long)]
+        label: Option<String>,
+        /// Abort before running anything if more packages than this would be removed
+        #[arg(long)]
+        max_removals: Option<usize>,
+        /// Proceed even when the max removals threshold is exceeded
+        #[arg(long)]
+        force: bool,
+        /// Print nothing when there's nothing to change, and a single summary line otherwise
+        #[arg(long)]
+        diff_only_on_change: bool,
+    },
     /// List dpmm generations
-    List,
+    List {
+        /// Leave out the generation_0 baseline
+        #[arg(long)]
+        no_baseline: bool,
+        /// Columns to print, in order
+        #[arg(long, value_delimiter = ',', default_value = "name,date,time,label")]
+        columns: Vec<ListColumn>,
+        /// Join the columns with this instead of aligning them, e.g. for cut or awk
+        #[arg(long)]
+        separator: Option<String>,
+    },
     /// List managed packaged managers
     Pm,
     /// Get config path
     Config,
+    /// Pull the latest config from dpmm.toml's source repository
+    Sync,
+    /// Validate dpmm.toml and the manager files without touching anything
+    Check,
     /// Rollsback to a previous generation
     Rollback {
         /// Optional: Generation name
         generation: Option<String>,
+        /// Restore the config files as they were before the last rollback
+        #[arg(long, conflicts_with = "generation")]
+        undo: bool,
+        /// Pick the generation from a numbered menu of recent ones
+        #[arg(long, conflicts_with_all = ["generation", "undo"])]
+        select: bool,
     },
+    /// Show package changes between the config and the latest generation, or between two generations
+    Diff {
+        /// Optional: Generation to diff from, defaults to the latest generation
+        from: Option<String>,
+        /// Optional: Generation to diff to, defaults to the current config
+        to: Option<String>,
+    },
+    /// Show which manager and file declare a package, since when it's installed, and why
+    Why { package: String },
     /// Update package list
     Update {
         /// You can pass the manager name to update it specifically, or `all` to update all managers
         manager: String,
+        /// Optional: Packages to update, passed in place of the command's `$` placeholder
+        packages: Vec<String>,
     },
     /// Upgrade packages
     Upgrade {
         /// You can pass the manager name to upgrade it specifically, `all` to upgrade all managers
         manager: String,
+        /// Optional: Packages to upgrade, passed in place of the command's `$` placeholder
+        packages: Vec<String>,
+    },
+    /// Print a generation as TOML, or `current` for the one computed from the config
+    Show {
+        /// Generation name or number, or `current`
+        generation: String,
+    },
+    /// Show the changes that created a generation, or the last few generations
+    Log {
+        /// Optional: Generation name or number
+        generation: Option<String>,
+    },
+    /// List stored generations that reference managers no longer in dpmm.toml
+    Orphans,
+    /// Delete old generations, either all before a generation or all but the newest few,
+    /// or those identical to their predecessor
+    #[command(group(ArgGroup::new("mode").required(true).args(["before", "keep", "duplicates"])))]
+    Prune {
+        /// Delete generations numbered before this one
+        #[arg(long)]
+        before: Option<String>,
+        /// Keep only this many of the newest generations
+        #[arg(long)]
+        keep: Option<usize>,
+        /// Delete generations recording the same packages as their predecessor, keeping the
+        /// oldest of each identical run
+        #[arg(long)]
+        duplicates: bool,
+        /// Also delete the generation_0 baseline
+        #[arg(long)]
+        include_baseline: bool,
+    },
+    /// Print the absolute path of a generation file, for scripts
+    Path {
+        /// Optional: Generation name or number, defaults to the latest generation
+        generation: Option<String>,
+    },
+    /// Show the generations, newest first, with how many packages each added and removed
+    History {
+        /// Show at most this many generations
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Record what's actually installed, per the managers' list_installed, as a new generation
+    Reconcile,
+    /// Delete all generations from the cache directory, leaving files dpmm didn't create
+    Clean {
+        /// Also delete the generation_0 baseline
+        #[arg(long)]
+        include_baseline: bool,
+    },
+    /// Collapse an inclusive range of generations into the last one of the range
+    Squash {
+        /// First generation of the range
+        from: String,
+        /// Last generation of the range, whose state is kept
+        to: String,
+    },
+    /// Print the JSON Schema of a config file, for editor completion and validation
+    Schema {
+        #[arg(value_enum)]
+        file: SchemaFile,
+    },
+    /// Backfill fields missing from generations written by older dpmm versions
+    Migrate,
+    /// Bring dpmm.toml up to the current schema_version
+    MigrateConfig,
+    /// Add packages to a manager's config
+    Add {
+        /// Manager name
+        manager: String,
+        /// Packages to add
+        packages: Vec<String>,
+        /// Also read newline-separated package names from stdin
+        #[arg(long)]
+        stdin: bool,
+        /// Install the added packages right away and record them in a new generation
+        #[arg(long)]
+        install: bool,
+    },
+    /// Create the config directory with an empty dpmm.toml
+    Init,
+    /// Write a skeleton <name>.toml for a new manager and register it in dpmm.toml
+    NewManager {
+        name: String,
+        /// Overwrite an existing <name>.toml
+        #[arg(long)]
+        force: bool,
     },
 }
 
-fn extract_gen(s: &fs::DirEntry) -> i32 {
-    s.file_name()
-        .to_string_lossy()
-        .trim_start_matches("generation_")
-        .trim_end_matches(".toml")
-        .parse::<i32>()
-        .unwrap_or(-1)
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum SchemaFile {
+    /// dpmm.toml
+    Dpmm,
+    /// A manager's <manager>.toml
+    Manager,
 }
 
-fn generation_files(dir: impl AsRef<Path>) -> anyhow::Result<Vec<fs::DirEntry>> {
-    let mut paths: Vec<_> = fs::read_dir(dir.as_ref())?.filter_map(Result::ok).collect();
-    paths.sort_by_key(extract_gen);
-    Ok(paths.into_iter().rev().collect())
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum ListColumn {
+    /// The generation's file stem
+    Name,
+    Date,
+    Time,
+    /// The label given on switch, or (baseline)
+    Label,
 }
 
-fn get_gen_file(dir: impl AsRef<Path>, idx: usize) -> Option<(PathBuf, u32)> {
-    let paths = generation_files(dir.as_ref()).ok()?;
-    let f = paths.get(idx);
-    if let Some(f) = f {
-        let n = extract_gen(f);
-        if n == -1 {
-            None
-        } else {
-            Some((f.path(), n as u32))
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum ColorChoice {
+    /// Color when stdout is a terminal and NO_COLOR isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Quotes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
         }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Wraps `text` in the ANSI color `code` when coloring is enabled.
+fn paint(text: &str, code: u8, color: bool) -> String {
+    if color {
+        format!("\x1b[{code}m{text}\x1b[0m")
     } else {
-        None
+        text.to_string()
     }
 }
 
-fn diff_unique(old: &[String], new: &[String]) -> (Vec<String>, Vec<String>) {
-    let old_set: HashSet<_> = old.iter().cloned().collect();
-    let new_set: HashSet<_> = new.iter().cloned().collect();
-    let added = new_set.difference(&old_set).cloned().collect();
-    let removed = old_set.difference(&new_set).cloned().collect();
-    (added, removed)
+/// The per-manager `+`/`-` lines of the packages `new` adds and removes.
+fn diff_lines(old: &Generation, new: &Generation, color: bool) -> Vec<String> {
+    let mut lines = vec![];
+    for (mname, added, removed) in diff_generations(old, new) {
+        if added.is_empty() && removed.is_empty() {
+            continue;
+        }
+        lines.push(format!("{mname}:"));
+        for a in added {
+            lines.push(paint(&format!("+ {a}"), 32, color));
+        }
+        for r in removed {
+            lines.push(paint(&format!("- {r}"), 31, color));
+        }
+    }
+    lines
 }
 
-fn resolve_changes(
-    manager: &Dpm,
-    added: &[String],
-    removed: &[String],
-    dry_run: bool,
-) -> anyhow::Result<()> {
-    if added.is_empty() && removed.is_empty() {
-        println!(
-            "Nothing to resolve with {}!",
-            manager.name.as_ref().unwrap()
-        );
-        return Ok(());
+fn print_diff(old: &Generation, new: &Generation, color: bool) {
+    for line in diff_lines(old, new, color) {
+        println!("{line}");
     }
-    let supports_multi = manager.supports_multi_args.unwrap_or(true);
-    if !removed.is_empty() {
-        if supports_multi {
-            let uninstall_cmd = manager.uninstall.replace("$", &removed.join(" "));
-            let cmd_n_args: Vec<_> = uninstall_cmd.split_whitespace().collect();
-            let mut cmd = Command::new(cmd_n_args[0]);
-            cmd.args(&cmd_n_args[1..]);
-            if dry_run {
-                println!("Uninstalls:\n{cmd:?}");
-            } else {
-                cmd.spawn()?.wait()?;
+}
+
+/// The generation before `n` among the stored `numbers`, newest first, which `n` was switched
+/// from. The first one had nothing before it.
+fn predecessor(cache: &Path, stem: &str, numbers: &[u32], n: u32) -> anyhow::Result<Generation> {
+    Ok(match numbers.iter().find(|&&p| p < n) {
+        Some(p) => read_generation(cache, stem, &p.to_string())?,
+        None => Generation::default(),
+    })
+}
+
+/// Says the manager is skipped for lacking the `step`, e.g. upgrade, command.
+fn no_command_note(name: &str, step: &str) -> String {
+    format!("Manager '{name}' has no {step} command defined; skipping")
+}
+
+/// Runs the update or upgrade command, picked by `command`, of the targeted managers,
+/// for just `packages` if any are given.
+fn run_maintenance(
+    managers: &[Dpm],
+    selected: &HashSet<String>,
+    target: &str,
+    packages: &[String],
+    label: &str,
+    command: impl Fn(&Dpm) -> Option<&CommandTemplate>,
+    opts: &RunOptions,
+) -> anyhow::Result<Vec<(String, Duration)>> {
+    let mut timings = vec![];
+    let (mut succeeded, mut failed) = (vec![], vec![]);
+    let targets = if target == "all" {
+        HashSet::new()
+    } else {
+        match_managers(managers, &[target.to_string()])?
+    };
+    // same order as a switch, so e.g. system repos refresh before language managers
+    for d in order_by_after(managers)? {
+        if interrupted() {
+            anyhow::bail!("Interrupted");
+        }
+        let name = d.name.clone().unwrap_or_default();
+        let targeted = if target == "all" {
+            d.include_in_all.unwrap_or(true)
+        } else {
+            targets.contains(&name)
+        };
+        if !targeted || !selected.contains(&name) {
+            continue;
+        }
+        let step = label.trim_end_matches('s').to_lowercase();
+        let Some(template) = command(d) else {
+            if target != "all" || opts.verbose {
+                println!("{}", no_command_note(&name, &step));
+            }
+            continue;
+        };
+        if packages.is_empty() && template.has_placeholder() {
+            // e.g. cargo and pip can only upgrade the packages they're given
+            if target == "all" {
+                println!(
+                    "Skipping {name}, its {step} command takes packages, run `dpmm {step} {name} <packages>`"
+                );
+                continue;
             }
+            anyhow::bail!(
+                "{name}'s {step} command has a package placeholder, pass the packages to {step}"
+            );
+        }
+        if !packages.is_empty() && !template.has_placeholder() {
+            anyhow::bail!("{name}'s {step} command has no placeholder to pass packages to");
+        }
+        // pinned packages need an invocation each for `$version`
+        let invocations: Vec<_> = if packages.is_empty()
+            || (d.supports_multi_args.unwrap_or(true)
+                && !template.has_version_placeholder()
+                && !template.takes_one_package())
+        {
+            vec![template.argv(&name, packages)]
         } else {
-            for rem in removed {
-                let uninstall_cmd = manager.uninstall.replace("$", rem);
-                let cmd_n_args: Vec<_> = uninstall_cmd.split_whitespace().collect();
-                let mut cmd = Command::new(cmd_n_args[0]);
-                cmd.args(&cmd_n_args[1..]);
-                if dry_run {
-                    println!("Uninstalls:\n{cmd:?}");
-                } else {
-                    cmd.spawn()?.wait()?;
+            packages
+                .iter()
+                .map(|p| template.argv(&name, std::slice::from_ref(p)))
+                .collect()
+        };
+        let env = manager_env(d)?;
+        let shown_env = masked_env(&env);
+        let start = Instant::now();
+        let mut result = Ok(());
+        for cmd_n_args in invocations {
+            if cmd_n_args.is_empty() {
+                return Err(DpmError::EmptyCommand {
+                    manager: name,
+                    step: label.to_lowercase(),
                 }
+                .into());
             }
-        }
-    }
-    if !added.is_empty() {
-        if supports_multi {
-            let install_cmd = manager.install.replace("$", &added.join(" "));
-            let cmd_n_args: Vec<_> = install_cmd.split_whitespace().collect();
-            let mut cmd = Command::new(cmd_n_args[0]);
-            cmd.args(&cmd_n_args[1..]);
-            if dry_run {
-                println!("Installs:\n{cmd:?}");
+            let cmd_str = shell_join(&cmd_n_args);
+            let note = if opts.dry_run {
+                not_found_note(&cmd_n_args[0])
             } else {
-                cmd.spawn()?.wait()?;
+                ""
+            };
+            if opts.print_commands {
+                println!("{shown_env}{cmd_str}{note}");
             }
-        } else {
-            for a in added {
-                let uninstall_cmd = manager.install.replace("$", a);
-                let cmd_n_args: Vec<_> = uninstall_cmd.split_whitespace().collect();
-                let mut cmd = Command::new(cmd_n_args[0]);
-                cmd.args(&cmd_n_args[1..]);
-                if dry_run {
-                    println!("Installs:\n{cmd:?}");
+            if opts.dry_run {
+                if !opts.print_commands {
+                    println!("{label}:\n{shown_env}{cmd_str}{note}");
+                }
+                continue;
+            }
+            result = spawn_and_wait(
+                manager_command(d, &cmd_n_args).envs(env.iter().cloned()),
+                &name,
+                opts,
+            )
+            .and_then(|status| {
+                if d.accepts(status) {
+                    Ok(())
                 } else {
-                    cmd.spawn()?.wait()?;
+                    Err(DpmError::CommandFailed {
+                        manager: name.clone(),
+                        command: cmd_str,
+                        status,
+                    })
                 }
+            });
+            if result.is_err() {
+                break;
             }
         }
+        if opts.dry_run {
+            continue;
+        }
+        timings.push((format!("{name} ({step})"), start.elapsed()));
+        match result {
+            Ok(()) => succeeded.push(name),
+            Err(e) if opts.continue_on_error => {
+                eprintln!("{e}");
+                failed.push(name);
+            }
+            Err(e) => return Err(e.into()),
+        }
     }
-    Ok(())
+    if opts.continue_on_error && !(succeeded.is_empty() && failed.is_empty()) {
+        if !succeeded.is_empty() {
+            println!("Succeeded: {}", succeeded.join(", "));
+        }
+        if !failed.is_empty() {
+            println!("Failed: {}", failed.join(", "));
+            anyhow::bail!(
+                "{} of {} managers failed",
+                failed.len(),
+                failed.len() + succeeded.len()
+            );
+        }
+    }
+    Ok(timings)
 }
 
-fn main() -> anyhow::Result<()> {
-    let home = PathBuf::from(env::var("HOME").context("No HOME directory set")?);
-    let config = if let Ok(p) = env::var(CONFIG_HOME) {
-        PathBuf::from(p).join("dpmm")
-    } else {
-        home.join(".config").join("dpmm")
-    };
-    let dpmm_toml = fs::read_to_string(config.join("dpmm.toml"))?;
-    let cache = if let Ok(p) = env::var(CACHE_HOME) {
-        PathBuf::from(p).join("dpmm")
-    } else {
-        home.join(".cache").join("dpmm")
+/// The time taken by each manager, slowest first, and in total.
+fn timing_lines(mut timings: Vec<(String, Duration)>) -> Vec<String> {
+    timings.sort_by_key(|(_, elapsed)| std::cmp::Reverse(*elapsed));
+    let total: Duration = timings.iter().map(|(_, elapsed)| *elapsed).sum();
+    timings
+        .into_iter()
+        .chain([("total".to_string(), total)])
+        .map(|(name, elapsed)| format!("  {name}\t{:.2}s", elapsed.as_secs_f64()))
+        .collect()
+}
+
+fn print_timings(timings: Vec<(String, Duration)>) {
+    println!("Timings:");
+    for line in timing_lines(timings) {
+        println!("{line}");
+    }
+}
+
+/// Asks which of the recent generations, other than the latest, to roll back to.
+fn select_generation(cache: &Path, stem: &str) -> anyhow::Result<u32> {
+    if !io::stdin().is_terminal() {
+        anyhow::bail!("rollback --select needs an interactive terminal");
+    }
+    pick_generation(cache, stem, &mut io::stdin().lock())
+}
+
+/// Lists recent generations and reads the chosen one's index from `input`.
+fn pick_generation(cache: &Path, stem: &str, input: &mut impl BufRead) -> anyhow::Result<u32> {
+    let entries: Vec<_> = generation_files(cache, stem)?
+        .into_iter()
+        .filter(|e| extract_gen(e, stem) != -1)
+        .skip(1)
+        .take(10)
+        .collect();
+    if entries.is_empty() {
+        anyhow::bail!("No earlier generation to roll back to");
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        let generation = load_generation(&entry.path()).ok();
+        let time = generation_time(entry, generation.as_ref())
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        println!(
+            "{}) {stem}{}\t{time}\t{}",
+            i + 1,
+            extract_gen(entry, stem),
+            generation.and_then(|g| g.label).unwrap_or_default()
+        );
+    }
+    print!("Roll back to [1-{}]: ", entries.len());
+    io::stdout().flush()?;
+    let answer = read_answer(input)?.unwrap_or_default();
+    let entry = answer
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| entries.get(i.checked_sub(1)?))
+        .with_context(|| format!("Invalid selection {}", answer.trim()))?;
+    Ok(extract_gen(entry, stem) as u32)
+}
+
+/// The absolute path of the `generation` file, or of the latest one.
+fn generation_path(cache: &Path, stem: &str, generation: Option<&str>) -> anyhow::Result<PathBuf> {
+    let path = match generation {
+        Some(generation) => gen_ref_path(cache, stem, generation),
+        None => {
+            get_gen_file(cache, stem, 0)
+                .ok_or_else(|| DpmError::GenerationNotFound("latest".to_string()))?
+                .0
+        }
     };
-    if dpmm_toml.is_empty() {
-        eprintln!("Empty dpmm.toml\nterminating!");
+    if !path.is_file() {
+        return Err(
+            DpmError::GenerationNotFound(generation.unwrap_or_default().to_string()).into(),
+        );
+    }
+    Ok(std::path::absolute(&path)?)
+}
+
+/// A generation of `dpmm history`, with the number of packages added and removed since its
+/// parent.
+struct HistoryEntry {
+    number: i32,
+    generation: Generation,
+    time: Option<chrono::DateTime<chrono::Local>>,
+    parent: Option<u32>,
+    added: usize,
+    removed: usize,
+}
+
+/// The latest `limit` generations, newest first.
+fn history(cache: &Path, stem: &str, limit: usize) -> anyhow::Result<Vec<HistoryEntry>> {
+    let entries: Vec<_> = generation_files(cache, stem)?
+        .into_iter()
+        .filter(|e| extract_gen(e, stem) != -1)
+        .collect();
+    let mut history = vec![];
+    for (i, entry) in entries.iter().take(limit).enumerate() {
+        let generation = load_generation(&entry.path())?;
+        // generations written before parents were recorded follow the previous one
+        let parent = generation
+            .parent
+            .or_else(|| entries.get(i + 1).map(|e| extract_gen(e, stem) as u32));
+        let (mut added, mut removed) = (0, 0);
+        if let Some(parent) = parent {
+            let old = read_generation(cache, stem, &parent.to_string()).unwrap_or_default();
+            for (_, a, r) in diff_generations(&old, &generation) {
+                added += a.len();
+                removed += r.len();
+            }
+        }
+        history.push(HistoryEntry {
+            number: extract_gen(entry, stem),
+            time: generation_time(entry, Some(&generation)),
+            generation,
+            parent,
+            added,
+            removed,
+        });
+    }
+    Ok(history)
+}
+
+/// Warns when generations exist but the generation_0 baseline they diff against doesn't.
+fn missing_baseline_warning(cache: &Path, stem: &str) -> Option<String> {
+    (!cache.join(format!("{stem}0.toml")).exists() && get_gen_file(cache, stem, 0).is_some()).then(
+        || {
+            format!(
+                "Warning: the {stem}0.toml baseline is missing, pass --repair to recreate it empty"
+            )
+        },
+    )
+}
+
+/// Config files are copied here before a rollback rewrites them, for `rollback --undo`.
+const ROLLBACK_BACKUP: &str = "pre-rollback-backup";
+
+/// Copies the .toml files in `from` into `to`.
+fn copy_tomls(from: &Path, to: &Path, dry_run: bool) -> anyhow::Result<()> {
+    if !dry_run {
+        fs::create_dir_all(to)?;
+    }
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "toml") {
+            continue;
+        }
+        let dest = to.join(path.file_name().context("Failed to get file name")?);
+        if dry_run {
+            println!("would copy {} to {}", path.display(), dest.display());
+        } else {
+            fs::copy(&path, &dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Walks up from `start` looking for a project-local `.dpm/dpmm.toml`, like `.git`.
+fn find_local_config(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(".dpm"))
+        .find(|dir| config_file(dir, "dpmm").is_file())
+}
+
+/// Where the checkout of a `source` repository lives in the cache directory.
+fn source_dir(cache: &Path, source: &str) -> PathBuf {
+    let name: String = source
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    cache.join("sources").join(name)
+}
+
+fn git(args: &[&OsStr]) -> anyhow::Result<()> {
+    let status = std::process::Command::new("git").args(args).status()?;
+    if !status.success() {
+        anyhow::bail!("git {} failed", args[0].display());
+    }
+    Ok(())
+}
+
+/// dpmm.toml's `source` and the directory of its checkout, which may not exist yet. Configs
+/// that don't load are left for the commands to report.
+fn source_checkout(config: &Path, cache: &Path) -> Option<(String, PathBuf)> {
+    let source = load_dpmm(config).ok()?.source?;
+    let dir = source_dir(cache, &source);
+    Some((source, dir))
+}
+
+/// Clones `source` into `dir`, only saying so under `dry_run`.
+fn clone_source(source: &str, dir: &Path, cache: &Path, dry_run: bool) -> anyhow::Result<()> {
+    if dry_run {
+        println!("Would clone {source} into {}", dir.display());
         return Ok(());
     }
-    if !cache.exists() {
-        fs::create_dir(&cache)?;
+    println!("Cloning {source} into {}", dir.display());
+    fs::create_dir_all(cache.join("sources"))?;
+    git(&[
+        "clone".as_ref(),
+        "--depth=1".as_ref(),
+        source.as_ref(),
+        dir.as_os_str(),
+    ])
+}
+
+/// Brings the checkout of dpmm.toml's `source` up to date, cloning it if needed.
+fn sync(config: &Path, cache: &Path, dry_run: bool) -> anyhow::Result<()> {
+    let source = load_dpmm(config)?
+        .source
+        .with_context(|| format!("{} has no source", config_file(config, "dpmm").display()))?;
+    let dir = source_dir(cache, &source);
+    if !dir.exists() {
+        return clone_source(&source, &dir, cache, dry_run);
     }
-    let dpmm: Dpmm = toml::from_str(&dpmm_toml)?;
-    let mut managers: Vec<Dpm> = vec![];
-    for manager in &dpmm.managers {
-        let fname = format!("{manager}.toml");
-        let mut toml: Dpm = toml::from_str(&fs::read_to_string(config.join(&fname))?)?;
-        toml.name = Some(manager.clone());
-        managers.push(toml);
-    }
-    let latest_gen = get_gen_file(&cache, 0);
-    let (latest_gen, n) = if let Some(f) = latest_gen {
-        (toml::from_str(&fs::read_to_string(f.0)?)?, f.1)
-    } else {
-        let gen0 = cache.join("generation_0.toml");
-        let mut managers0 = managers.clone();
-        for manager in &mut managers0 {
-            manager.packages.clear();
+    if dry_run {
+        println!("Would pull {source} into {}", dir.display());
+        return Ok(());
+    }
+    git(&[
+        "-C".as_ref(),
+        dir.as_os_str(),
+        "pull".as_ref(),
+        "--ff-only".as_ref(),
+    ])
+}
+
+/// Errors for config files dpmm can't rewrite, which are those not written in toml.
+fn ensure_editable(path: &Path) -> anyhow::Result<()> {
+    if path.exists() && path.extension().is_none_or(|e| e != "toml") {
+        anyhow::bail!("{} isn't a toml file, dpmm only edits toml", path.display());
+    }
+    Ok(())
+}
+
+/// The manager as written in its config, `written`, with its `packages` rolled back to those
+/// `recorded` in a generation, so presets, vars and everything else stay as the user wrote
+/// them. Packages the config adds from elsewhere, groups, includes or `packages_cmd`, are
+/// left to those, `current` being the manager as the config resolves it now.
+fn roll_back_packages(
+    mut written: Dpm,
+    recorded: &Dpm,
+    current: Option<&Dpm>,
+    vars: &BTreeMap<String, String>,
+) -> std::result::Result<Dpm, String> {
+    let mut own = written.clone();
+    own.apply_vars(vars)?;
+    let elsewhere: Vec<&Package> = current
+        .map_or(&[][..], |c| c.packages.as_slice())
+        .iter()
+        .filter(|p| !own.packages.iter().any(|o| o.name == p.name))
+        .collect();
+    for p in &elsewhere {
+        if !recorded.packages.iter().any(|r| r.name == p.name) {
+            println!(
+                "Note: {} of {} isn't in the manager's packages but comes from a group, an include or packages_cmd, which the rollback leaves as they are",
+                p.name,
+                recorded.name.as_deref().unwrap_or_default()
+            );
+        }
+    }
+    written.packages = recorded
+        .packages
+        .iter()
+        .filter(|r| !elsewhere.iter().any(|p| p.name == r.name))
+        .map(|r| {
+            // the entry as written, e.g. with its `{vars.<name>}`, when it's the one recorded
+            match own.packages.iter().position(|o| o.same_entry(r)) {
+                Some(i) => written.packages[i].clone(),
+                None => r.clone(),
+            }
+        })
+        .collect();
+    Ok(written)
+}
+
+/// `path:line` of a key, for pointing at the offending line of a toml file.
+fn locate(path: &Path, item: Option<&toml_edit::Item>, text: &str) -> String {
+    match item.and_then(toml_edit::Item::span) {
+        Some(span) => format!(
+            "{}:{}",
+            path.display(),
+            text[..span.start].matches('\n').count() + 1
+        ),
+        None => path.display().to_string(),
+    }
+}
+
+/// The rows `list` prints, a cell per column for each generation in the `stem` series.
+fn list_rows(
+    cache: &Path,
+    stem: &str,
+    no_baseline: bool,
+    columns: &[ListColumn],
+) -> anyhow::Result<Vec<Vec<String>>> {
+    let paths = generation_files(cache, stem)?;
+    let mut rows = vec![];
+    for path in paths {
+        let p = &path;
+        // other hosts' generations, and the rollback backup
+        let number = extract_gen(p, stem);
+        if number == -1 || (number == 0 && no_baseline) {
+            continue;
         }
-        let managers0 = Generation {
-            managers: managers0,
+        let baseline = number == 0;
+        let generation = fs::read_to_string(p.path())
+            .ok()
+            .and_then(|s| toml::from_str::<Generation>(&s).ok());
+        let (date, time) = match generation_time(p, generation.as_ref()) {
+            Some(time) => (time.date_naive().to_string(), time.time().to_string()),
+            None => ("unknown".to_string(), "unknown".to_string()),
         };
-        fs::write(&gen0, toml::to_string(&managers0)?.as_bytes())?;
-        // assuming the above worked!
-        (managers0, 0)
+        let label = if baseline {
+            "(baseline)".to_string()
+        } else {
+            generation.and_then(|g| g.label).unwrap_or_default()
+        };
+        let name = p
+            .path()
+            .file_stem()
+            .context("Failed to get stem")?
+            .to_str()
+            .context("Failed to convert file name to str")?
+            .to_string();
+        rows.push(
+            columns
+                .iter()
+                .map(|column| match column {
+                    ListColumn::Name => name.clone(),
+                    ListColumn::Date => date.clone(),
+                    ListColumn::Time => time.clone(),
+                    ListColumn::Label => label.clone(),
+                })
+                .collect::<Vec<_>>(),
+        );
+    }
+    Ok(rows)
+}
+
+/// The `rows` of `dpmm list` joined by `separator`, or aligned in columns without one.
+fn list_lines(rows: &[Vec<String>], separator: Option<&str>) -> Vec<String> {
+    let columns = rows.first().map_or(0, Vec::len);
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| rows.iter().map(|r| r[i].chars().count()).max().unwrap_or(0))
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let line = match separator {
+                Some(separator) => row.join(separator),
+                None => row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{cell:width$}"))
+                    .collect::<Vec<_>>()
+                    .join("  "),
+            };
+            line.trim_end().to_string()
+        })
+        .collect()
+}
+
+/// The generation_0 baseline of `managers`, with the packages and repos `installed` records
+/// for them, which the first switch then doesn't install again.
+fn baseline_generation(managers: &[Dpm], installed: &Generation) -> Generation {
+    let mut managers0 = managers.to_vec();
+    for manager in &mut managers0 {
+        let seed = installed.managers.iter().find(|m| m.name == manager.name);
+        manager.packages = seed.map(|m| m.packages.clone()).unwrap_or_default();
+        // so the first switch adds them too
+        manager.repos = seed.and_then(|m| m.repos.clone());
+    }
+    Generation::new(managers0)
+}
+
+/// The generation the latest one was switched from, which a rollback without one returns to.
+fn previous_generation(cache: &Path, stem: &str, latest: &Generation) -> anyhow::Result<u32> {
+    if let Some(parent) = latest.parent {
+        return Ok(parent);
+    }
+    // generations written before parents were recorded
+    Ok(get_gen_file(cache, stem, 1)
+        .context("Failed to get last generation file")?
+        .1)
+}
+
+/// The generations other than the `doomed` ones that were switched from one of them, now
+/// pointing at what it was switched from in turn. `numbers` are newest first, as are their
+/// `generations`.
+fn reparent(numbers: &[u32], generations: &[Generation], doomed: &[u32]) -> Vec<(u32, Generation)> {
+    let parent_of = |n: u32| {
+        let i = numbers.iter().position(|m| *m == n)?;
+        generations[i]
+            .parent
+            .or_else(|| numbers.get(i + 1).copied())
     };
+    let mut reparented = vec![];
+    for (n, generation) in numbers.iter().zip(generations) {
+        if doomed.contains(n) {
+            continue;
+        }
+        let mut parent = generation.parent;
+        while let Some(p) = parent
+            && doomed.contains(&p)
+        {
+            parent = parent_of(p);
+        }
+        if parent != generation.parent {
+            reparented.push((
+                *n,
+                Generation {
+                    parent,
+                    ..generation.clone()
+                },
+            ));
+        }
+    }
+    reparented
+}
 
-    let current_gen = Generation { managers };
+/// Refuses a switch from `old` to `new` removing more than `max` packages, before anything runs.
+fn check_removals(old: &Generation, new: &Generation, max: Option<usize>) -> anyhow::Result<()> {
+    let removals = plan_generation(old, new)?.removals();
+    if let Some(max) = max
+        && removals > max
+    {
+        anyhow::bail!(
+            "Refusing to remove {removals} packages, more than the max removals threshold of {max}. Pass --force to proceed"
+        );
+    }
+    Ok(())
+}
 
-    let args = Args::parse();
-    match &args.command {
-        Commands::Switch => {
-            let mut changed = false;
-            for m in &current_gen.managers {
-                let mname = m.name.as_ref().unwrap();
-                // ignore removed managers
-                if let Some(corresp) = latest_gen
-                    .managers
-                    .iter()
-                    .find(|manager| manager.name == Some(mname.clone()))
-                {
-                    let (added, removed) = diff_unique(&corresp.packages, &m.packages);
-                    resolve_changes(m, &added, &removed, args.dry_run)?;
-                    changed = !removed.is_empty() || !added.is_empty();
-                } else {
-                    resolve_changes(m, &m.packages, &[], args.dry_run)?;
-                    changed = true;
-                }
+/// The stored generations recording managers that are no longer in dpmm.toml, with those
+/// managers' names.
+fn orphans(cache: &Path, stem: &str, dpmm: &Dpmm) -> anyhow::Result<Vec<(String, Vec<String>)>> {
+    let mut found = vec![];
+    for path in generation_files(cache, stem)? {
+        if extract_gen(&path, stem) == -1 {
+            continue;
+        }
+        let p = path.path();
+        let generation = load_generation(&p)?;
+        let stale: Vec<_> = generation
+            .managers
+            .iter()
+            .filter_map(|m| m.name.clone())
+            .filter(|name| !dpmm.contains(name))
+            .collect();
+        if !stale.is_empty() {
+            let name = p.file_stem().context("Failed to get stem")?;
+            found.push((name.to_string_lossy().into_owned(), stale));
+        }
+    }
+    Ok(found)
+}
+
+/// Adds `packages` to a manager's list, a pinned package replacing the entry of the same name.
+fn add_packages(list: &mut Vec<Package>, packages: &[String]) {
+    for p in packages {
+        let p = Package::parse(p);
+        match list.iter_mut().find(|e| e.name == p.name) {
+            Some(entry) if p.version.is_some() => *entry = p,
+            Some(_) => (),
+            None => list.push(p),
+        }
+    }
+}
+
+/// Validates the config without running anything, reporting every problem found rather
+/// than stopping at the first.
+fn check_config(config: &Path) -> anyhow::Result<()> {
+    let dpmm_path = config_file(config, "dpmm");
+    let dpmm = load_dpmm(config)?;
+    let dpmm_text = fs::read_to_string(&dpmm_path).unwrap_or_default();
+    let mut problems = vec![];
+    let mut loaded = vec![];
+    let vars = dpmm.vars.clone().unwrap_or_default();
+    if let Err(e) = load_included_packages(config, &dpmm) {
+        problems.push(e.to_string());
+    }
+    for (i, entry) in dpmm.managers.iter().enumerate() {
+        let (path, text) = if entry.inline.is_some() {
+            (dpmm_path.clone(), dpmm_text.clone())
+        } else {
+            let path = config_file(config, &entry.name);
+            let text = fs::read_to_string(&path).unwrap_or_default();
+            (path, text)
+        };
+        let mut dpm = match load_entry(config, entry) {
+            Ok(dpm) => dpm,
+            Err(DpmError::MissingManager(name)) => {
+                problems.push(format!(
+                    "{}: missing, but {name} is listed in dpmm.toml",
+                    path.display()
+                ));
+                continue;
             }
-            if changed {
-                let t = toml::to_string(&current_gen)?;
-                if !args.dry_run {
-                    fs::write(cache.join(format!("generation_{}.toml", n + 1)), t)?;
-                } else {
-                    println!("writes to generation_{}.toml:\n{t}", n + 1);
-                }
+            Err(e) => {
+                problems.push(e.to_string());
+                continue;
             }
+        };
+        if let Err(message) = dpm.apply_vars(&vars) {
+            problems.push(format!("{}: {message}", path.display()));
         }
-        Commands::Rollback { generation } => {
-            let new_gen_file: String = if let Some(generation) = generation {
-                fs::read_to_string(cache.join(format!("{generation}.toml")))?
-            } else {
-                fs::read_to_string(
-                    get_gen_file(&cache, 1)
-                        .context("Failed to get last generation file")?
-                        .0,
-                )?
-            };
-            let new_gen: Generation = toml::from_str(&new_gen_file)?;
-            let mut names = vec![];
-            for m in &new_gen.managers {
-                let mname = m.name.as_ref().unwrap();
-                names.push(mname.clone());
-                // ignore removed managers
-                if let Some(corresp) = latest_gen
-                    .managers
-                    .iter()
-                    .find(|manager| manager.name == Some(mname.clone()))
-                {
-                    let (added, removed) = diff_unique(&corresp.packages, &m.packages);
-                    resolve_changes(m, &added, &removed, args.dry_run)?;
-                } else {
-                    resolve_changes(m, &m.packages, &[], args.dry_run)?;
-                }
-                let t = toml::to_string::<Dpm>(m)?;
-                if !args.dry_run {
-                    fs::write(config.join(format!("{mname}.toml")), t)?;
-                } else {
-                    println!("writes to {mname}.toml:\n{t}");
-                }
+        let mut active = dpm.clone();
+        entry.options.apply(&mut active);
+        loaded.push(active);
+        // json files have no spans to point at
+        let doc = toml_edit::ImDocument::parse(text.as_str()).ok();
+        let table = doc.as_ref().and_then(|doc| match &entry.inline {
+            Some(_) => {
+                let managers = doc.get("managers")?;
+                managers
+                    .get(i)
+                    .or_else(|| managers.get(entry.name.as_str()))
             }
-            let dpmm: String = toml::to_string(&Dpmm { managers: names })?;
-            if !args.dry_run {
-                fs::write(config.join("dpmm.toml"), dpmm)?;
-            } else {
-                println!("writes to dpmm.toml:\n{dpmm}");
+            None => Some(doc.as_item()),
+        });
+        let at = |key: &str| locate(&path, table.and_then(|t| t.get(key)), &text);
+        for (field, template) in [
+            ("install", Some(&dpm.install)),
+            ("uninstall", dpm.uninstall.as_ref()),
+            ("install_user", dpm.install_user.as_ref()),
+            ("install_system", dpm.install_system.as_ref()),
+            ("uninstall_user", dpm.uninstall_user.as_ref()),
+            ("uninstall_system", dpm.uninstall_system.as_ref()),
+            ("update", dpm.update.as_ref()),
+            ("upgrade", dpm.upgrade.as_ref()),
+            ("pre_install", dpm.pre_install.as_ref()),
+            ("post_install", dpm.post_install.as_ref()),
+            ("pre_uninstall", dpm.pre_uninstall.as_ref()),
+            ("post_uninstall", dpm.post_uninstall.as_ref()),
+            ("add_repo", dpm.add_repo.as_ref()),
+            ("remove_repo", dpm.remove_repo.as_ref()),
+        ] {
+            let Some(template) = template else {
+                continue;
+            };
+            if template.is_empty() {
+                problems.push(format!("{}: `{field}` is empty", at(field)));
+            } else if (field.starts_with("install") || field.starts_with("uninstall"))
+                && !template.has_placeholder()
+            {
+                problems.push(format!(
+                    "{}: `{field}` is missing the `$` or `{{packages}}` placeholder",
+                    at(field)
+                ));
             }
         }
-        Commands::List => {
-            let paths = generation_files(&cache)?;
-            for path in paths {
-                let p = &path;
-                let time = chrono::DateTime::<chrono::Local>::from(p.metadata()?.created()?);
-                println!(
-                    "{}\t\t{}\t\t{}",
-                    p.path()
-                        .file_stem()
-                        .context("Failed to get stem")?
-                        .to_str()
-                        .context("Failed to convert file name to str")?,
-                    time.date_naive(),
-                    time.time()
-                );
-            }
+        if dpm.packages.iter().any(|p| is_pattern(&p.name)) && dpm.list_available.is_none() {
+            problems.push(format!(
+                "{}: wildcard packages need a `list_available` command",
+                at("packages")
+            ));
         }
-        Commands::Update { manager } => {
-            if args.dry_run {
-                for d in current_gen.managers {
-                    if d.name == Some(manager.to_string()) || manager == "all" {
-                        if let Some(update) = d.update {
-                            println!("Updates:\n{}", update);
-                        }
-                    }
-                }
-            } else {
-                for d in current_gen.managers {
-                    if d.name == Some(manager.to_string()) || manager == "all" {
-                        if let Some(update) = d.update {
-                            let cmd_n_args: Vec<_> = update.split_whitespace().collect();
-                            let mut d = Command::new(cmd_n_args[0]);
-                            d.args(&cmd_n_args[1..]);
-                            d.spawn()?.wait()?;
-                        }
-                    }
-                }
+        if let Some(min_version) = &dpm.min_version {
+            if dpm.version_check.is_none() {
+                problems.push(format!(
+                    "{}: `min_version` needs a `version_check` command",
+                    at("min_version")
+                ));
+            } else if find_version(min_version) != Some(min_version.as_str()) {
+                problems.push(format!(
+                    "{}: `min_version` {min_version} isn't a version number",
+                    at("min_version")
+                ));
             }
         }
-        Commands::Upgrade { manager } => {
-            if args.dry_run {
-                for d in current_gen.managers {
-                    if d.name == Some(manager.to_string()) || manager == "all" {
-                        if let Some(upgrade) = d.upgrade {
-                            println!("Upgrades:\n{}", upgrade);
-                        }
-                    }
-                }
-            } else {
-                for d in current_gen.managers {
-                    if d.name == Some(manager.to_string()) || manager == "all" {
-                        if let Some(upgrade) = d.upgrade {
-                            let cmd_n_args: Vec<_> = upgrade.split_whitespace().collect();
-                            let mut d = Command::new(cmd_n_args[0]);
-                            d.args(&cmd_n_args[1..]);
-                            d.spawn()?.wait()?;
-                        }
-                    }
-                }
-            }
+        if dpm.repos.as_ref().is_some_and(|r| !r.is_empty()) && dpm.add_repo.is_none() {
+            problems.push(format!(
+                "{}: `repos` needs an `add_repo` command",
+                at("repos")
+            ));
         }
-        Commands::Pm => {
-            for m in &dpmm.managers {
-                println!("{}", m);
-            }
+        if dpm.packages.iter().any(|p| p.name.trim().is_empty()) {
+            problems.push(format!("{}: a package has an empty name", at("packages")));
+        }
+        if let Some(env_file) = &dpm.env_file
+            && !config
+                .join(expand_path(&env_file.to_string_lossy()))
+                .is_file()
+        {
+            problems.push(format!(
+                "{}: `env_file` {} doesn't exist",
+                at("env_file"),
+                env_file.display()
+            ));
+        }
+        if let Some(cwd) = &dpm.cwd
+            && !config.join(expand_path(&cwd.to_string_lossy())).is_dir()
+        {
+            problems.push(format!(
+                "{}: `cwd` {} isn't a directory",
+                at("cwd"),
+                cwd.display()
+            ));
+        }
+    }
+    if let Some(env_file) = &dpmm.env_file
+        && !config
+            .join(expand_path(&env_file.to_string_lossy()))
+            .is_file()
+    {
+        problems.push(format!(
+            "{}: `env_file` {} doesn't exist",
+            locate(
+                &dpmm_path,
+                toml_edit::ImDocument::parse(dpmm_text.as_str())
+                    .ok()
+                    .as_ref()
+                    .and_then(|doc| doc.get("env_file")),
+                &dpmm_text
+            ),
+            env_file.display()
+        ));
+    }
+    for (profile, p) in dpmm.profiles.iter().flatten() {
+        for name in p.managers.iter().flatten() {
+            if !dpmm.contains(name) {
+                problems.push(format!(
+                    "{}: profile {profile} uses {name}, which isn't a manager in dpmm.toml",
+                    dpmm_path.display()
+                ));
+            }
+        }
+    }
+    for (name, by) in duplicate_packages(&loaded) {
+        let message = format!("{name} is declared by {}", by.join(", "));
+        match dpmm.duplicate_packages.unwrap_or_default() {
+            DuplicatePolicy::Allow => (),
+            DuplicatePolicy::Warn => eprintln!("Warning: {message}"),
+            DuplicatePolicy::Error => problems.push(format!("{}: {message}", dpmm_path.display())),
+        }
+    }
+    if problems.is_empty() {
+        println!("Config is valid");
+        return Ok(());
+    }
+    for problem in &problems {
+        eprintln!("{problem}");
+    }
+    anyhow::bail!("Found {} problems in the config", problems.len())
+}
+
+/// The single line `switch --diff-only-on-change` prints when something changed.
+fn switch_summary(generation: &str, summary: &ChangeSummary, format: OutputFormat) -> String {
+    let changed = summary
+        .managers
+        .iter()
+        .filter(|m| m.new || !m.added.is_empty() || !m.removed.is_empty() || !m.updated.is_empty());
+    let added: usize = summary.managers.iter().map(|m| m.added.len()).sum();
+    let removed = summary.removals();
+    if format == OutputFormat::Json {
+        let list = |pkgs: &[String]| {
+            pkgs.iter()
+                .map(|p| json_string(p))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let managers = changed
+            .map(|m| {
+                format!(
+                    "{{\"name\":{},\"added\":[{}],\"removed\":[{}]}}",
+                    json_string(&m.name),
+                    list(&m.added),
+                    list(&m.removed)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "{{\"generation\":{},\"added\":{added},\"removed\":{removed},\"managers\":[{managers}]}}",
+            json_string(generation)
+        )
+    } else {
+        let managers = changed
+            .map(|m| format!("{} +{} -{}", m.name, m.added.len(), m.removed.len()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{generation}: +{added} -{removed} ({managers})")
+    }
+}
+
+fn init(config: &Path, dry_run: bool) -> anyhow::Result<()> {
+    let path = config_file(config, "dpmm");
+    if path.exists() {
+        anyhow::bail!("{} already exists", path.display());
+    }
+    let text = format!(
+        "schema_version = {SCHEMA_VERSION}\n\
+         # Managers in this directory, apt maps to apt.toml. Add them with `dpmm new-manager <name>`\n\
+         managers = []\n"
+    );
+    if dry_run {
+        println!("writes to {}:\n{text}", path.display());
+        return Ok(());
+    }
+    fs::create_dir_all(config)?;
+    fs::write(&path, text)?;
+    println!("Created {}", path.display());
+    Ok(())
+}
+
+fn new_manager(config: &Path, name: &str, force: bool, dry_run: bool) -> anyhow::Result<()> {
+    ensure_editable(&config_file(config, "dpmm"))?;
+    let path = config_file(config, name);
+    ensure_editable(&path)?;
+    if path.exists() && !force {
+        anyhow::bail!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        );
+    }
+    let skeleton = format!(
+        "# Replace these with {name}'s actual commands, `$` is replaced by the packages\n\
+         update = \"{name} update\"\n\
+         upgrade = \"{name} upgrade\"\n\
+         install = \"{name} install $\"\n\
+         uninstall = \"{name} uninstall $\"\n\
+         \n\
+         packages = []\n"
+    );
+    let dpmm = match load_dpmm(config) {
+        Err(DpmError::ConfigNotFound(_)) => Dpmm::default(),
+        res => res?,
+    };
+    let dpmm_path = config.join("dpmm.toml");
+    let registered = if dpmm.contains(name) {
+        None
+    } else {
+        let mut managers = dpmm.managers.clone();
+        managers.push(ManagerEntry::from(name));
+        Some(edit_toml(&dpmm_path, &Dpmm { managers, ..dpmm })?)
+    };
+    if dry_run {
+        println!("writes to {name}.toml:\n{skeleton}");
+        if let Some(t) = registered {
+            println!("writes to dpmm.toml:\n{t}");
+        }
+        return Ok(());
+    }
+    fs::create_dir_all(config)?;
+    fs::write(&path, skeleton)?;
+    if let Some(t) = registered {
+        fs::write(&dpmm_path, t)?;
+    }
+    println!("Created {}", path.display());
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let result = run(Args::parse());
+    // the command that was killed by Ctrl-C failed, which stopped everything before recording
+    if result.is_err() && interrupted() {
+        eprintln!("Interrupted; state not recorded");
+        std::process::exit(130);
+    }
+    result
+}
+
+fn run(args: Args) -> anyhow::Result<()> {
+    let home = PathBuf::from(env::var("HOME").context("No HOME directory set")?);
+    let local = if args.global {
+        None
+    } else {
+        find_local_config(&env::current_dir()?)
+    };
+    // an explicit directory wins over one found by looking around
+    let config = if let Some(dir) = args
+        .config
+        .as_ref()
+        .map(|dir| expand_path(&dir.to_string_lossy()))
+        .or_else(|| env::var("DPM_CONFIG_DIR").ok().map(|dir| expand_path(&dir)))
+    {
+        dir
+    } else if let Some(local) = local {
+        local
+    } else if let Ok(p) = env::var(CONFIG_HOME) {
+        PathBuf::from(p).join("dpmm")
+    } else {
+        home.join(".config").join("dpmm")
+    };
+    let cache = if let Ok(p) = env::var(CACHE_HOME) {
+        PathBuf::from(p).join("dpmm")
+    } else {
+        home.join(".cache").join("dpmm")
+    };
+    run_in(args, config, cache)
+}
+
+/// Runs the command with the config and cache directories it uses.
+fn run_in(args: Args, config: PathBuf, cache: PathBuf) -> anyhow::Result<()> {
+    if let Commands::Sync = args.command {
+        return sync(&config, &cache, args.dry_run);
+    }
+    // a dpmm.toml with a source only points at the real config, which the commands below
+    // that don't change anything read only once it's checked out
+    let checkout = source_checkout(&config, &cache);
+    let config = match &checkout {
+        Some((_, dir)) if dir.exists() => dir.clone(),
+        _ => config,
+    };
+    if let Commands::Check = args.command {
+        return check_config(&config);
+    }
+    if let Commands::MigrateConfig = args.command {
+        let path = config_file(&config, "dpmm");
+        ensure_editable(&path)?;
+        match migrate_config(&path)? {
+            None => println!(
+                "{} is already at schema_version {SCHEMA_VERSION}",
+                path.display()
+            ),
+            Some(t) if args.dry_run => println!("writes to {}:\n{t}", path.display()),
+            Some(t) => {
+                fs::write(&path, t)?;
+                println!(
+                    "Migrated {} to schema_version {SCHEMA_VERSION}",
+                    path.display()
+                );
+            }
+        }
+        return Ok(());
+    }
+    if let Commands::Init = args.command {
+        return init(&config, args.dry_run);
+    }
+    if let Commands::NewManager { name, force } = &args.command {
+        return new_manager(&config, name, *force, args.dry_run);
+    }
+    if let Commands::Schema { file } = args.command {
+        match file {
+            SchemaFile::Dpmm => print!("{}", schema::DPMM_SCHEMA),
+            SchemaFile::Manager => print!("{}", schema::MANAGER_SCHEMA),
+        }
+        return Ok(());
+    }
+    let config = match checkout {
+        Some((source, dir)) if !dir.exists() => {
+            clone_source(&source, &dir, &cache, args.dry_run)?;
+            if args.dry_run {
+                return Ok(());
+            }
+            dir
+        }
+        _ => config,
+    };
+    if fs::metadata(config_file(&config, "dpmm")).is_ok_and(|m| m.len() == 0) {
+        eprintln!("Empty dpmm.toml\nterminating!");
+        return Ok(());
+    }
+    let mut dpmm = load_dpmm(&config)?;
+    install_interrupt_handler();
+    let opts = RunOptions {
+        dry_run: args.dry_run,
+        print_commands: args.print_commands,
+        prefix_output: args.prefix_output,
+        verbose: args.verbose,
+        assume_yes: args.yes,
+        strict: args.strict,
+        continue_on_error: args.continue_on_error,
+        quiet: false,
+        jobs: args.jobs,
+    };
+    // e.g. a `git pull`, so the switch uses the config it leaves behind
+    if let Commands::Switch { .. } = args.command
+        && let Some(hook) = &dpmm.pre_switch
+    {
+        run_hook(hook, &config, "Pre-switch", &opts)?;
+        dpmm = load_dpmm(&config)?;
+    }
+    if !cache.exists() && !args.dry_run {
+        fs::create_dir(&cache)?;
+    }
+    let profile = args.profile.clone().or_else(|| dpmm.profile.clone());
+    let dpmm_profile = profile
+        .as_ref()
+        .and_then(|name| dpmm.profiles.as_ref()?.get(name))
+        .cloned();
+    if let Some(names) = dpmm_profile.as_ref().and_then(|p| p.managers.as_ref()) {
+        if let Some(name) = names.iter().find(|name| !dpmm.contains(name)) {
+            anyhow::bail!(
+                "Profile {} uses {name}, which isn't a manager in dpmm.toml",
+                profile.as_deref().unwrap_or_default()
+            );
+        }
+        dpmm.managers.retain(|entry| names.contains(&entry.name));
+    }
+    let mut managers: Vec<Dpm> = vec![];
+    let mut profile_found = dpmm_profile.is_some();
+    let groups = if !args.groups.is_empty() {
+        args.groups.clone()
+    } else if let Some(groups) = dpmm_profile.and_then(|p| p.groups) {
+        groups
+    } else {
+        dpmm.groups.clone().unwrap_or_default()
+    };
+    let mut groups_found = HashSet::new();
+    let mut included = load_included_packages(&config, &dpmm)?;
+    let vars = dpmm.vars.clone().unwrap_or_default();
+    let host = args.host.clone().or_else(hostname);
+    for entry in &dpmm.managers {
+        let mut manager = load_entry(&config, entry)?;
+        extend_packages(
+            &mut manager.packages,
+            included.remove(&entry.name).unwrap_or_default(),
+        );
+        manager
+            .apply_vars(&vars)
+            .map_err(|message| DpmError::ParseError {
+                file: if entry.inline.is_some() {
+                    config_file(&config, "dpmm")
+                } else {
+                    config_file(&config, &entry.name)
+                },
+                message,
+            })?;
+        entry.options.apply(&mut manager);
+        // relative to the config directory
+        manager.env_file = manager
+            .env_file
+            .or_else(|| dpmm.env_file.clone())
+            .map(|path| config.join(expand_path(&path.to_string_lossy())));
+        manager.cwd = manager
+            .cwd
+            .map(|path| config.join(expand_path(&path.to_string_lossy())));
+        groups_found.extend(manager.apply_groups(&groups));
+        if let Some(host) = &host {
+            manager.apply_host(host);
+        }
+        if let Some(profile) = &profile
+            && manager.apply_profile(profile)
+        {
+            profile_found = true;
+        }
+        // packages for other platforms aren't managed here at all
+        manager.packages.retain(Package::is_active);
+        resolve_packages_cmd(&mut manager)?;
+        resolve_patterns(&mut manager)?;
+        managers.push(manager);
+    }
+    if let Some(profile) = &profile
+        && !profile_found
+    {
+        anyhow::bail!("No manager has a profile named {profile}");
+    }
+    if let Some(group) = groups.iter().find(|g| !groups_found.contains(g.as_str())) {
+        anyhow::bail!("No manager has a group named {group}");
+    }
+    let stem = generation_stem(
+        args.host_prefix.as_deref().or(dpmm.host_prefix.as_deref()),
+        profile.as_deref(),
+    );
+    let latest_gen = get_gen_file(&cache, &stem, 0);
+    let gen0 = cache.join(format!("{stem}0.toml"));
+    let baseline_missing = !gen0.exists();
+    if !baseline_missing && args.assume_installed.is_some() {
+        eprintln!("{stem}0.toml already exists, ignoring --assume-installed");
+    }
+    if !args.repair
+        && let Some(warning) = missing_baseline_warning(&cache, &stem)
+    {
+        eprintln!("{warning}");
+    }
+    let mut baseline = None;
+    if baseline_missing && (latest_gen.is_none() || args.repair) {
+        let installed = match &args.assume_installed {
+            Some(path) => {
+                let path = expand_path(&path.to_string_lossy());
+                load_generation(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?
+            }
+            None => Generation::default(),
+        };
+        let managers0 = baseline_generation(&managers, &installed);
+        if args.dry_run {
+            println!("would create {stem}0.toml");
+        } else {
+            fs::write(&gen0, toml::to_string(&managers0)?.as_bytes())?;
+        }
+        baseline = Some(managers0);
+    }
+    let (latest_gen, n) = match (latest_gen, baseline) {
+        (Some(f), _) => (load_generation(&f.0)?, f.1),
+        // assuming the above worked!
+        (None, Some(baseline)) => (baseline, 0),
+        (None, None) => unreachable!("the baseline is created when there's no generation"),
+    };
+
+    let current_gen = Generation {
+        managers,
+        ..Default::default()
+    };
+
+    let mut selected: HashSet<String> = if args.only.is_empty() {
+        current_gen
+            .managers
+            .iter()
+            .filter_map(|m| m.name.clone())
+            .collect()
+    } else {
+        match_managers(&current_gen.managers, &args.only)?
+    };
+    for name in match_managers(&current_gen.managers, &args.exclude)? {
+        selected.remove(&name);
+    }
+    // disabled managers, or those for other OSes, are left out like excluded ones
+    for m in &current_gen.managers {
+        if !m.is_active() {
+            selected.remove(m.name.as_ref().unwrap());
+        }
+    }
+
+    let color = args.color.enabled();
+    let mut timings = vec![];
+    match &args.command {
+        Commands::Switch {
+            label,
+            max_removals,
+            force,
+            diff_only_on_change,
+        } => {
+            let opts = RunOptions {
+                quiet: *diff_only_on_change,
+                ..opts
+            };
+            // managers left out by --only/--exclude aren't touched
+            let selected_gen = Generation {
+                managers: current_gen
+                    .managers
+                    .iter()
+                    .filter(|m| m.name.as_ref().is_some_and(|name| selected.contains(name)))
+                    .cloned()
+                    .collect(),
+                ..Default::default()
+            };
+            for (name, by) in duplicate_packages(&selected_gen.managers) {
+                let message = format!("{name} is declared by {}", by.join(", "));
+                match dpmm.duplicate_packages.unwrap_or_default() {
+                    DuplicatePolicy::Allow => (),
+                    DuplicatePolicy::Warn => eprintln!("Warning: {message}"),
+                    DuplicatePolicy::Error => anyhow::bail!(
+                        "{message}, set duplicate_packages = \"warn\" in dpmm.toml to allow it"
+                    ),
+                }
+            }
+            if !force {
+                check_removals(
+                    &latest_gen,
+                    &selected_gen,
+                    max_removals.or(dpmm.max_removals),
+                )?;
+            }
+            let summary = apply_generation(&latest_gen, &selected_gen, &opts)?;
+            for m in &summary.managers {
+                timings.push((m.name.clone(), m.elapsed));
+            }
+            if interrupted() {
+                eprintln!("Interrupted; state not recorded");
+                std::process::exit(130);
+            }
+            if summary.changed() {
+                // managers left out by --only/--exclude keep their previously recorded state
+                let managers = current_gen
+                    .managers
+                    .iter()
+                    .filter_map(|m| {
+                        if selected.contains(m.name.as_ref()?) {
+                            // removals declined at the prompt are still installed, failed
+                            // optional packages aren't
+                            let mut m = m.clone();
+                            if let Some(changes) = summary
+                                .managers
+                                .iter()
+                                .find(|c| m.name.as_ref() == Some(&c.name))
+                            {
+                                // a reinstall whose removal was declined keeps its new entry
+                                let declined: Vec<Package> = changes
+                                    .declined
+                                    .iter()
+                                    .map(|p| Package::parse(p))
+                                    .filter(|p| !m.packages.contains(p))
+                                    .collect();
+                                m.packages.extend(declined);
+                                m.packages.retain(|p| !changes.failed.contains(&p.spec()));
+                            }
+                            Some(m)
+                        } else {
+                            latest_gen
+                                .managers
+                                .iter()
+                                .find(|l| l.name == m.name)
+                                .cloned()
+                        }
+                    })
+                    .collect();
+                let t = toml::to_string(&Generation {
+                    config_hash: Some(config_hash(&current_gen.managers)?),
+                    label: label.clone(),
+                    parent: Some(n),
+                    ..Generation::new(managers)
+                })?;
+                if !args.dry_run {
+                    write_atomic(&cache.join(format!("{stem}{}.toml", n + 1)), t)?;
+                } else if !diff_only_on_change {
+                    println!("writes to {stem}{}.toml:\n{t}", n + 1);
+                }
+                if *diff_only_on_change {
+                    let generation = format!("{stem}{}", n + 1);
+                    println!("{}", switch_summary(&generation, &summary, args.format));
+                }
+            }
+            if let Some(hook) = &dpmm.post_switch {
+                run_hook(hook, &config, "Post-switch", &opts)?;
+            }
+        }
+        Commands::Rollback { undo: true, .. } => {
+            let backup = cache.join(ROLLBACK_BACKUP);
+            if !backup.is_dir() {
+                anyhow::bail!("No rollback to undo");
+            }
+            copy_tomls(&backup, &config, args.dry_run)?;
+            if !args.dry_run {
+                println!(
+                    "Restored the config from before the last rollback, run `dpmm switch` to apply it"
+                );
+            }
+        }
+        Commands::Rollback {
+            generation, select, ..
+        } => {
+            let target = if *select {
+                select_generation(&cache, &stem)?.to_string()
+            } else if let Some(generation) = generation {
+                generation.clone()
+            } else {
+                previous_generation(&cache, &stem, &latest_gen)?.to_string()
+            };
+            let new_gen = read_generation(&cache, &stem, &target)?;
+            // a rollback rewrites the config files
+            ensure_editable(&config_file(&config, "dpmm"))?;
+            for m in &new_gen.managers {
+                let name = m.name.as_deref().unwrap_or_default();
+                if dpmm
+                    .managers
+                    .iter()
+                    .any(|e| e.name == name && e.inline.is_some())
+                {
+                    continue;
+                }
+                ensure_editable(&config_file(&config, name))?;
+            }
+            if !args.dry_run
+                && !select
+                && !confirm(
+                    &format!(
+                        "Roll back to {}, rewriting the config in {}?",
+                        gen_ref_path(&cache, &stem, &target)
+                            .file_stem()
+                            .context("Failed to get stem")?
+                            .to_string_lossy(),
+                        config.display()
+                    ),
+                    &opts,
+                )?
+            {
+                println!("Rollback aborted");
+                return Ok(());
+            }
+            let backup = cache.join(ROLLBACK_BACKUP);
+            if !args.dry_run && backup.exists() {
+                fs::remove_dir_all(&backup)?;
+            }
+            copy_tomls(&config, &backup, args.dry_run)?;
+            let mut names = vec![];
+            for m in &new_gen.managers {
+                let mname = m.name.as_ref().unwrap();
+                // keep the dpmm.toml options of managers still around
+                let mut entry = dpmm
+                    .managers
+                    .iter()
+                    .find(|e| &e.name == mname)
+                    .cloned()
+                    .unwrap_or_else(|| ManagerEntry::from(mname.as_str()));
+                // ignore removed managers
+                if let Some(corresp) = latest_gen
+                    .managers
+                    .iter()
+                    .find(|manager| manager.name == Some(mname.clone()))
+                {
+                    let (added, removed) = diff_unique(
+                        &package_specs(&corresp.packages),
+                        &package_specs(&m.packages),
+                    );
+                    check_version(m, &opts)?;
+                    add_repos(m, Some(corresp), &opts)?;
+                    resolve_changes(m, Some(corresp), &added, &removed, &opts)?;
+                    remove_repos(m, Some(corresp), &opts)?;
+                } else {
+                    check_version(m, &opts)?;
+                    add_repos(m, None, &opts)?;
+                    resolve_changes(m, None, &package_specs(&m.packages), &[], &opts)?;
+                }
+                if interrupted() {
+                    eprintln!("Interrupted; state not recorded");
+                    std::process::exit(130);
+                }
+                let current = current_gen
+                    .managers
+                    .iter()
+                    .find(|c| c.name.as_ref() == Some(mname));
+                // managers defined inline are rewritten along with dpmm.toml
+                if let Some(inline) = &mut entry.inline {
+                    **inline = roll_back_packages(Dpm::clone(inline), m, current, &vars).map_err(
+                        |message| DpmError::ParseError {
+                            file: config_file(&config, "dpmm"),
+                            message,
+                        },
+                    )?;
+                    names.push(entry);
+                    continue;
+                }
+                names.push(entry);
+                let path = config.join(format!("{mname}.toml"));
+                // a manager removed since is written back as it was recorded
+                let t = if path.exists() {
+                    // not load_manager, which would write the name into the file
+                    let written: Dpm = toml::from_str(&fs::read_to_string(&path)?)?;
+                    let rolled_back =
+                        roll_back_packages(written, m, current, &vars).map_err(|message| {
+                            DpmError::ParseError {
+                                file: path.clone(),
+                                message,
+                            }
+                        })?;
+                    edit_toml(&path, &rolled_back)?
+                } else {
+                    edit_toml(&path, m)?
+                };
+                if !args.dry_run {
+                    fs::write(path, t)?;
+                } else {
+                    println!("writes to {mname}.toml:\n{t}");
+                }
+            }
+            let dpmm: String = edit_toml(
+                &config.join("dpmm.toml"),
+                &Dpmm {
+                    managers: names,
+                    ..dpmm.clone()
+                },
+            )?;
+            if !args.dry_run {
+                fs::write(config.join("dpmm.toml"), dpmm)?;
+            } else {
+                println!("writes to dpmm.toml:\n{dpmm}");
+            }
+        }
+        Commands::List {
+            no_baseline,
+            columns,
+            separator,
+        } => {
+            let rows = list_rows(&cache, &stem, *no_baseline, columns)?;
+            for line in list_lines(&rows, separator.as_deref()) {
+                println!("{line}");
+            }
+        }
+        Commands::Diff { from, to } => {
+            let old = match from {
+                Some(from) => read_generation(&cache, &stem, from)?,
+                None => latest_gen.clone(),
+            };
+            let new = match to {
+                Some(to) => read_generation(&cache, &stem, to)?,
+                None => current_gen.clone(),
+            };
+            print_diff(&old, &new, color);
+        }
+        Commands::Why { package } => {
+            let declared: Vec<(&ManagerEntry, &Dpm, &Package)> =
+                dpmm.managers
+                    .iter()
+                    .filter_map(|entry| {
+                        let m = current_gen
+                            .managers
+                            .iter()
+                            .find(|m| m.name.as_ref() == Some(&entry.name))?;
+                        let p = m.packages.iter().find(|p| {
+                            &p.name == package || p.installed_as.as_ref() == Some(package)
+                        })?;
+                        Some((entry, m, p))
+                    })
+                    .collect();
+            if declared.is_empty() {
+                anyhow::bail!("{package} isn't declared by any manager");
+            }
+            let entries = generation_files(&cache, &stem)?;
+            for (entry, m, p) in declared {
+                match package_source(&config, &dpmm, entry, &p.name)? {
+                    Some(file) => println!(
+                        "{} is declared by {} in {}",
+                        p.spec(),
+                        entry.name,
+                        file.display()
+                    ),
+                    None => println!("{} is declared by {}'s packages_cmd", p.spec(), entry.name),
+                }
+                if let Some(reason) = &p.reason {
+                    println!("  reason: {reason}");
+                }
+                if let Some(tags) = &p.tags {
+                    println!("  tags: {}", tags.join(", "));
+                }
+                // the oldest of the newest generations recording it without a break
+                let mut since = None;
+                for e in entries.iter().filter(|e| extract_gen(e, &stem) != -1) {
+                    let generation = load_generation(&e.path())?;
+                    let recorded = generation
+                        .managers
+                        .iter()
+                        .any(|o| o.name == m.name && o.packages.iter().any(|o| o.name == p.name));
+                    if !recorded {
+                        break;
+                    }
+                    since = Some((extract_gen(e, &stem), generation_time(e, Some(&generation))));
+                }
+                match since {
+                    Some((number, time)) => println!(
+                        "  installed since {stem}{number}{}",
+                        time.map(|t| t.format(" on %Y-%m-%d %H:%M:%S").to_string())
+                            .unwrap_or_default()
+                    ),
+                    None => println!("  not installed yet, run `dpmm switch`"),
+                }
+            }
+        }
+        Commands::Log { generation } => {
+            let numbers = generation_numbers(&cache, &stem)?;
+            let shown: Vec<u32> = match generation {
+                Some(generation) => {
+                    let n = gen_ref_number(&stem, generation)
+                        .filter(|n| numbers.contains(n))
+                        .with_context(|| format!("No generation {generation}"))?;
+                    vec![n]
+                }
+                None => numbers.iter().copied().take(5).collect(),
+            };
+            for n in shown {
+                let new = read_generation(&cache, &stem, &n.to_string())?;
+                let old = predecessor(&cache, &stem, &numbers, n)?;
+                println!("{stem}{n}:");
+                print_diff(&old, &new, color);
+            }
+        }
+        Commands::Update { manager, packages } => {
+            timings = run_maintenance(
+                &current_gen.managers,
+                &selected,
+                manager,
+                packages,
+                "Updates",
+                |d| d.update.as_ref(),
+                &opts,
+            )?;
+        }
+        Commands::Upgrade { manager, packages } => {
+            timings = run_maintenance(
+                &current_gen.managers,
+                &selected,
+                manager,
+                packages,
+                "Upgrades",
+                |d| d.upgrade.as_ref(),
+                &opts,
+            )?;
+        }
+        Commands::Prune {
+            before,
+            keep,
+            duplicates,
+            include_baseline,
+        } => {
+            let numbers = generation_numbers(&cache, &stem)?;
+            let generations = numbers
+                .iter()
+                .map(|n| read_generation(&cache, &stem, &n.to_string()))
+                .collect::<Result<Vec<_>>>()?;
+            let doomed: Vec<u32> = if *duplicates {
+                // numbers are newest first, so the next one is the predecessor
+                numbers
+                    .iter()
+                    .zip(generations.windows(2))
+                    .filter(|(n, pair)| **n != 0 && pair[0].same_packages(&pair[1]))
+                    .map(|(n, _)| *n)
+                    .collect()
+            } else if let Some(before) = before {
+                let target = gen_ref_number(&stem, before)
+                    .filter(|n| numbers.contains(n))
+                    .ok_or_else(|| DpmError::GenerationNotFound(before.clone()))?;
+                numbers.iter().copied().filter(|n| *n < target).collect()
+            } else {
+                numbers
+                    .iter()
+                    .copied()
+                    .skip(keep.unwrap_or_default())
+                    .collect()
+            };
+            let doomed: Vec<u32> = doomed
+                .into_iter()
+                .filter(|n| *n != 0 || *include_baseline)
+                .collect();
+            for (n, generation) in reparent(&numbers, &generations, &doomed) {
+                let t = toml::to_string(&generation)?;
+                if args.dry_run {
+                    println!("writes to {stem}{n}.toml:\n{t}");
+                } else {
+                    write_atomic(&cache.join(format!("{stem}{n}.toml")), t)?;
+                }
+            }
+            for n in doomed {
+                if args.dry_run {
+                    println!("would remove {stem}{n}.toml");
+                } else {
+                    fs::remove_file(cache.join(format!("{stem}{n}.toml")))?;
+                    println!("Removed {stem}{n}");
+                }
+            }
+        }
+        Commands::Path { generation } => {
+            println!(
+                "{}",
+                generation_path(&cache, &stem, generation.as_deref())?.display()
+            );
+        }
+        Commands::History { limit } => {
+            let mut json = vec![];
+            for entry in history(&cache, &stem, limit.unwrap_or(usize::MAX))? {
+                let HistoryEntry {
+                    number,
+                    generation,
+                    time,
+                    parent,
+                    added,
+                    removed,
+                } = entry;
+                if args.format == OutputFormat::Json {
+                    let or_null =
+                        |s: Option<String>| s.map_or("null".to_string(), |s| json_string(&s));
+                    json.push(format!(
+                        "{{\"generation\":{number},\"created_at\":{},\"label\":{},\"parent\":{},\"added\":{added},\"removed\":{removed}}}",
+                        or_null(time.map(|t| t.to_rfc3339())),
+                        or_null(generation.label),
+                        parent.map_or("null".to_string(), |p| p.to_string()),
+                    ));
+                    continue;
+                }
+                let label = if number == 0 {
+                    "(baseline)".to_string()
+                } else {
+                    generation.label.unwrap_or_default()
+                };
+                println!(
+                    "{stem}{number}\t{}\t{}\t{label}",
+                    time.map_or("unknown".to_string(), |t| t
+                        .format("%Y-%m-%d %H:%M:%S")
+                        .to_string()),
+                    paint(&format!("+{added}"), 32, color)
+                        + " "
+                        + &paint(&format!("-{removed}"), 31, color),
+                );
+            }
+            if args.format == OutputFormat::Json {
+                println!("[{}]", json.join(",\n"));
+            }
+        }
+        Commands::Reconcile => {
+            let mut reconciled = Generation {
+                label: Some("reconcile".to_string()),
+                parent: Some(n),
+                // recorded from the system rather than produced from the config
+                config_hash: None,
+                ..Generation::new(latest_gen.managers.clone())
+            };
+            for m in &mut reconciled.managers {
+                let name = m.name.clone().unwrap_or_default();
+                if !selected.contains(&name) {
+                    continue;
+                }
+                // the manager's commands may have changed since the latest generation
+                let listed = match current_gen.managers.iter().find(|c| c.name == m.name) {
+                    Some(current) => list_installed(current)?,
+                    None => list_installed(m)?,
+                };
+                // packages installed under another name are recorded by their declared one
+                let known = current_gen
+                    .managers
+                    .iter()
+                    .find(|c| c.name == m.name)
+                    .map_or(&[][..], |c| c.packages.as_slice());
+                let declared = |p: Package| match known
+                    .iter()
+                    .find(|k| k.installed_as.as_ref() == Some(&p.name))
+                {
+                    Some(k) => Package {
+                        version: p.version,
+                        ..k.clone()
+                    },
+                    None => p,
+                };
+                match listed {
+                    Some(installed) => {
+                        m.packages = installed
+                            .iter()
+                            .map(|p| declared(Package::parse(p)))
+                            .collect()
+                    }
+                    None if args.verbose => {
+                        println!("Manager '{name}' has no list_installed command defined; skipping")
+                    }
+                    None => (),
+                }
+            }
+            if diff_generations(&latest_gen, &reconciled)
+                .iter()
+                .all(|(_, added, removed)| added.is_empty() && removed.is_empty())
+            {
+                println!("The latest generation already matches what's installed");
+                return Ok(());
+            }
+            print_diff(&latest_gen, &reconciled, color);
+            let t = toml::to_string(&reconciled)?;
+            if args.dry_run {
+                println!("writes to {stem}{}.toml:\n{t}", n + 1);
+            } else {
+                write_atomic(&cache.join(format!("{stem}{}.toml", n + 1)), t)?;
+            }
+        }
+        Commands::Clean { include_baseline } => {
+            for entry in generation_files(&cache, &stem)? {
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                // leftovers of interrupted atomic writes
+                let leftover = file_name
+                    .strip_suffix(".toml.tmp")
+                    .and_then(|f| f.strip_prefix(stem.as_str()))
+                    .is_some_and(|n| n.parse::<u32>().is_ok());
+                let number = extract_gen(&entry, &stem);
+                if !leftover && (number == -1 || (number == 0 && !include_baseline)) {
+                    continue;
+                }
+                if args.dry_run {
+                    println!("would remove {file_name}");
+                } else {
+                    fs::remove_file(entry.path())?;
+                    println!("Removed {file_name}");
+                }
+            }
+        }
+        Commands::Squash { from, to } => {
+            let numbers = generation_numbers(&cache, &stem)?;
+            let resolve = |generation: &String| {
+                gen_ref_number(&stem, generation)
+                    .filter(|n| numbers.contains(n))
+                    .ok_or_else(|| DpmError::GenerationNotFound(generation.clone()))
+            };
+            let (first, last) = (resolve(from)?, resolve(to)?);
+            if first > last {
+                anyhow::bail!("{stem}{first} comes after {stem}{last}");
+            }
+            if first == 0 {
+                anyhow::bail!("Refusing to squash the generation_0 baseline");
+            }
+            // the last generation takes the place of the whole range
+            let doomed: Vec<u32> = numbers
+                .iter()
+                .copied()
+                .filter(|n| (first..last).contains(n))
+                .collect();
+            let generations = numbers
+                .iter()
+                .map(|n| read_generation(&cache, &stem, &n.to_string()))
+                .collect::<Result<Vec<_>>>()?;
+            for (n, generation) in reparent(&numbers, &generations, &doomed) {
+                let t = toml::to_string(&generation)?;
+                if args.dry_run {
+                    println!("writes to {stem}{n}.toml:\n{t}");
+                } else {
+                    write_atomic(&cache.join(format!("{stem}{n}.toml")), t)?;
+                }
+            }
+            if args.dry_run {
+                for n in doomed {
+                    println!("would remove {stem}{n}.toml");
+                }
+            } else {
+                for n in &doomed {
+                    fs::remove_file(cache.join(format!("{stem}{n}.toml")))?;
+                }
+                println!(
+                    "Squashed {} generations into {stem}{last}",
+                    doomed.len() + 1
+                );
+            }
+        }
+        Commands::Migrate => {
+            let numbers = generation_numbers(&cache, &stem)?;
+            for (i, n) in numbers.iter().enumerate() {
+                let path = cache.join(format!("{stem}{n}.toml"));
+                let original = fs::read_to_string(&path)?;
+                let mut generation = read_generation(&cache, &stem, &n.to_string())?;
+                if generation.created_at.is_none() {
+                    let mtime: chrono::DateTime<chrono::Local> =
+                        fs::metadata(&path)?.modified()?.into();
+                    generation.created_at = Some(mtime.to_rfc3339());
+                }
+                if generation.dpm_version.is_none() {
+                    generation.dpm_version = Some("unknown".to_string());
+                }
+                // generations read as version 1 unchanged so far
+                generation.schema_version.get_or_insert(SCHEMA_VERSION);
+                // numbers are newest first, so the next one is the predecessor
+                if generation.parent.is_none() {
+                    generation.parent = numbers.get(i + 1).copied();
+                }
+                let t = toml::to_string(&generation)?;
+                if t == original {
+                    continue;
+                }
+                if args.dry_run {
+                    println!("writes to {stem}{n}.toml:\n{t}");
+                } else {
+                    write_atomic(&path, t)?;
+                    println!("Migrated {stem}{n}");
+                }
+            }
+        }
+        Commands::Add {
+            manager,
+            packages,
+            stdin,
+            install,
+        } => {
+            if !dpmm.contains(manager) {
+                return Err(DpmError::MissingManager(manager.clone()).into());
+            }
+            let mut packages = packages.clone();
+            if *stdin {
+                packages.extend(read_package_list(io::stdin().lock())?);
+            }
+            // managers defined inline are edited in dpmm.toml
+            let mut entries = dpmm.managers.clone();
+            let entry = entries
+                .iter_mut()
+                .find(|e| &e.name == manager)
+                .expect("checked above");
+            let path = if entry.inline.is_some() {
+                config_file(&config, "dpmm")
+            } else {
+                config_file(&config, manager)
+            };
+            ensure_editable(&path)?;
+            let mut dpm: Dpm = match &entry.inline {
+                Some(inline) => Dpm::clone(inline),
+                // not load_manager, which would write the name into the file
+                None => toml::from_str(&fs::read_to_string(&path)?)?,
+            };
+            add_packages(&mut dpm.packages, &packages);
+            let t = if let Some(inline) = &mut entry.inline {
+                **inline = dpm;
+                edit_toml(
+                    &path,
+                    &Dpmm {
+                        managers: entries,
+                        ..dpmm.clone()
+                    },
+                )?
+            } else {
+                edit_toml(&path, &dpm)?
+            };
+            if !args.dry_run {
+                fs::write(&path, t)?;
+            } else {
+                println!("writes to {}:\n{t}", path.display());
+            }
+            if !*install {
+                return Ok(());
+            }
+            // only the added packages are applied, other changes to the config wait for a switch
+            let mut m = current_gen
+                .managers
+                .iter()
+                .find(|m| m.name.as_ref() == Some(manager))
+                .cloned()
+                .expect("loaded from dpmm.toml");
+            if !m.is_active() {
+                anyhow::bail!("{manager} is disabled or not used on this OS, not installing");
+            }
+            let old = latest_gen
+                .managers
+                .iter()
+                .find(|o| o.name == m.name)
+                .cloned();
+            m.packages = old.as_ref().map(|o| o.packages.clone()).unwrap_or_default();
+            add_packages(&mut m.packages, &packages);
+            let summary = apply_generation(
+                &Generation {
+                    managers: old.into_iter().collect(),
+                    ..Default::default()
+                },
+                &Generation {
+                    managers: vec![m.clone()],
+                    ..Default::default()
+                },
+                &opts,
+            )?;
+            if interrupted() {
+                eprintln!("Interrupted; state not recorded");
+                std::process::exit(130);
+            }
+            if !summary.changed() {
+                return Ok(());
+            }
+            if let Some(changes) = summary.managers.first() {
+                m.packages.retain(|p| !changes.failed.contains(&p.spec()));
+            }
+            let mut managers = latest_gen.managers.clone();
+            match managers.iter_mut().find(|o| o.name == m.name) {
+                Some(o) => *o = m,
+                None => managers.push(m),
+            }
+            let t = toml::to_string(&Generation {
+                label: Some(format!("add {manager}")),
+                parent: Some(n),
+                ..Generation::new(managers)
+            })?;
+            if !args.dry_run {
+                write_atomic(&cache.join(format!("{stem}{}.toml", n + 1)), t)?;
+            } else {
+                println!("writes to {stem}{}.toml:\n{t}", n + 1);
+            }
+        }
+        Commands::Show { generation } => {
+            let generation = if generation == "current" {
+                current_gen.clone()
+            } else {
+                read_generation(&cache, &stem, generation)?
+            };
+            print!("{}", toml::to_string(&generation)?);
+        }
+        Commands::Orphans => {
+            for (generation, stale) in orphans(&cache, &stem, &dpmm)? {
+                println!("{generation}\t\t{}", stale.join(", "));
+            }
+        }
+        Commands::Pm => {
+            for m in dpmm.names() {
+                println!("{}", m);
+            }
         }
         Commands::Config => {
             println!("{:?}", config);
         }
+        Commands::Check
+        | Commands::MigrateConfig
+        | Commands::Sync
+        | Commands::Init
+        | Commands::Schema { .. }
+        | Commands::NewManager { .. } => unreachable!(),
+    }
+    if args.time && !timings.is_empty() {
+        print_timings(timings);
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    fn manager(name: &str, packages: &[&str]) -> Dpm {
+        Dpm::builder()
+            .name(name)
+            .install("apt install $")
+            .packages(packages.iter().copied())
+            .build()
+    }
+
+    /// A manager file whose installs append their manager's name and packages to `log`.
+    #[cfg(unix)]
+    fn logging_manager(log: &Path, packages: &[&str]) -> String {
+        let script = format!("echo \"$0 $@\" >> {}", log.display());
+        format!(
+            "install = [\"sh\", \"-c\", {script:?}, \"{{manager}}\", \"{{packages}}\"]\nuninstall = \"true $\"\npackages = {packages:?}\n"
+        )
+    }
+
+    #[test]
+    fn check_config_rejects_malformed_managers() {
+        let dir = env::temp_dir().join(format!("dpmm-check-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("dpmm.toml"), "managers = [\"apt\", \"npm\"]\n").unwrap();
+        fs::write(dir.join("apt.toml"), "instal = \"apt install $\"\n").unwrap();
+        fs::write(dir.join("npm.toml"), "install = \"npm install -g\"\n").unwrap();
+        let result = check_config(&dir);
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        files.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Found 2 problems in the config"
+        );
+        assert_eq!(files, ["apt.toml", "dpmm.toml", "npm.toml"]);
+    }
+
+    #[test]
+    fn finds_local_config_upwards() {
+        let root = env::temp_dir().join(format!("dpmm-local-{}", std::process::id()));
+        let nested = root.join("src").join("deep");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(root.join(".dpm")).unwrap();
+        let before = find_local_config(&nested);
+        fs::write(root.join(".dpm").join("dpmm.toml"), "managers = []\n").unwrap();
+        let found = find_local_config(&nested);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(before, None);
+        assert_eq!(found, Some(root.join(".dpm")));
+    }
+
+    #[test]
+    fn adds_packages_read_from_stdin() {
+        let input = io::Cursor::new("# tools\nripgrep\n\n  fd  \ngit\nnode@22\n");
+        let packages = read_package_list(input).unwrap();
+        assert_eq!(packages, ["ripgrep", "fd", "git", "node@22"]);
+        let mut list: Vec<Package> = vec!["git".into(), "node@20".into()];
+        add_packages(&mut list, &packages);
+        assert_eq!(package_specs(&list), ["git", "node@22", "ripgrep", "fd"]);
+    }
+
+    #[test]
+    fn orphans_reports_removed_managers() {
+        let cache = env::temp_dir().join(format!("dpmm-orphans-{}", std::process::id()));
+        fs::create_dir_all(&cache).unwrap();
+        for (n, managers) in [(1, vec!["apt"]), (2, vec!["apt", "snap"])] {
+            let generation = Generation::new(managers.iter().map(|m| manager(m, &[])).collect());
+            let t = toml::to_string(&generation).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let dpmm: Dpmm = toml::from_str("managers = [\"apt\", \"npm\"]").unwrap();
+        let found = orphans(&cache, "generation_", &dpmm);
+        fs::remove_dir_all(&cache).unwrap();
+        assert_eq!(
+            found.unwrap(),
+            [("generation_2".to_string(), strings(&["snap"]))]
+        );
+    }
+
+    #[test]
+    fn list_shows_labels() {
+        let cache = env::temp_dir().join(format!("dpmm-labels-{}", std::process::id()));
+        fs::create_dir_all(&cache).unwrap();
+        for (n, label) in [(0, None), (1, Some("pre-upgrade")), (2, None)] {
+            let generation = Generation {
+                label: label.map(str::to_string),
+                ..Generation::new(vec![])
+            };
+            let t = toml::to_string(&generation).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let rows = list_rows(
+            &cache,
+            "generation_",
+            false,
+            &[ListColumn::Name, ListColumn::Label],
+        );
+        fs::remove_dir_all(&cache).unwrap();
+        assert_eq!(
+            rows.unwrap(),
+            [
+                ["generation_2", ""],
+                ["generation_1", "pre-upgrade"],
+                ["generation_0", "(baseline)"]
+            ]
+        );
+    }
+
+    #[test]
+    fn log_diffs_against_the_predecessor() {
+        let cache = env::temp_dir().join(format!("dpmm-log-{}", std::process::id()));
+        fs::create_dir_all(&cache).unwrap();
+        // generation_2 was pruned
+        for (n, packages) in [
+            (0, vec!["git"]),
+            (1, vec!["git", "fd"]),
+            (3, vec!["fd", "bat"]),
+        ] {
+            let t = toml::to_string(&Generation::new(vec![manager("apt", &packages)])).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let log = |n: u32| {
+            let numbers = generation_numbers(&cache, "generation_")?;
+            let new = read_generation(&cache, "generation_", &n.to_string())?;
+            let old = predecessor(&cache, "generation_", &numbers, n)?;
+            anyhow::Ok(diff_generations(&old, &new))
+        };
+        let (latest, first) = (log(3), log(0));
+        fs::remove_dir_all(&cache).unwrap();
+        let apt = |added, removed| ("apt".to_string(), strings(added), strings(removed));
+        assert_eq!(latest.unwrap(), [apt(&["bat"], &["git"])]);
+        assert_eq!(first.unwrap(), [apt(&["git"], &[])]);
+    }
+
+    #[test]
+    fn first_switch_diffs_against_assumed_installed() {
+        let installed = Generation {
+            managers: vec![manager("apt", &["git", "fd"])],
+            ..Default::default()
+        };
+        let managers = [
+            manager("apt", &["git", "fd", "bat"]),
+            manager("npm", &["pnpm"]),
+        ];
+        let baseline = baseline_generation(&managers, &installed);
+        let current = Generation {
+            managers: managers.to_vec(),
+            ..Default::default()
+        };
+        let plan = plan_generation(&baseline, &current).unwrap().managers;
+        let added: Vec<_> = plan
+            .iter()
+            .map(|m| (m.name.as_str(), &m.added[..]))
+            .collect();
+        assert_eq!(
+            added,
+            [
+                ("apt", &strings(&["bat"])[..]),
+                ("npm", &strings(&["pnpm"])[..])
+            ]
+        );
+        assert!(plan.iter().all(|m| m.removed.is_empty()));
+    }
+
+    #[test]
+    fn add_keeps_comments() {
+        let path = env::temp_dir().join(format!("dpmm-comments-{}.toml", std::process::id()));
+        let original = "# apt, for system packages\ninstall = \"apt install $\" # needs sudo\npackages = [\"git\"] # the essentials\n";
+        fs::write(&path, original).unwrap();
+        let mut apt: Dpm = toml::from_str(original).unwrap();
+        add_packages(&mut apt.packages, &strings(&["fd"]));
+        let edited = edit_toml(&path, &apt);
+        fs::remove_file(&path).unwrap();
+        let edited = edited.unwrap();
+        assert!(
+            edited.starts_with(
+                "# apt, for system packages\ninstall = \"apt install $\" # needs sudo\n"
+            )
+        );
+        assert!(edited.contains("# the essentials"));
+        let apt: Dpm = toml::from_str(&edited).unwrap();
+        assert_eq!(package_specs(&apt.packages), ["git", "fd"]);
+    }
+
+    #[test]
+    fn list_marks_and_drops_the_baseline() {
+        let cache = env::temp_dir().join(format!("dpmm-baseline-{}", std::process::id()));
+        fs::create_dir_all(&cache).unwrap();
+        for n in 0..2 {
+            let t = toml::to_string(&Generation::new(vec![])).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let columns = [ListColumn::Name, ListColumn::Label];
+        let rows = |no_baseline| list_rows(&cache, "generation_", no_baseline, &columns);
+        let (all, no_baseline) = (rows(false), rows(true));
+        fs::remove_dir_all(&cache).unwrap();
+        assert_eq!(
+            all.unwrap(),
+            [["generation_1", ""], ["generation_0", "(baseline)"]]
+        );
+        assert_eq!(no_baseline.unwrap(), [["generation_1", ""]]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn upgrade_all_skips_managers_left_out_of_all() {
+        let log = env::temp_dir().join(format!("dpmm-upgrade-all-{}", std::process::id()));
+        let upgrading = |name: &str| {
+            let script = format!("echo {name} >> {}", log.display());
+            Dpm::builder()
+                .name(name)
+                .install("true $")
+                .upgrade(strings(&["sh", "-c", &script]))
+        };
+        let managers = [
+            upgrading("apt").build(),
+            upgrading("mirror").include_in_all(false).build(),
+        ];
+        let selected: HashSet<String> = managers.iter().filter_map(|m| m.name.clone()).collect();
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        for target in ["all", "mirror"] {
+            run_maintenance(
+                &managers,
+                &selected,
+                target,
+                &[],
+                "Upgrades",
+                |d| d.upgrade.as_ref(),
+                &opts,
+            )
+            .unwrap();
+        }
+        let logged = fs::read_to_string(&log).unwrap();
+        fs::remove_file(&log).unwrap();
+        assert_eq!(logged, "apt\nmirror\n");
+    }
+
+    #[test]
+    fn max_removals_refuses_large_switches() {
+        let apt = |packages| Generation {
+            managers: vec![manager("apt", packages)],
+            ..Default::default()
+        };
+        let (old, new) = (apt(&["git", "fd", "bat"]), apt(&["git"]));
+        assert_eq!(
+            check_removals(&old, &new, Some(1)).unwrap_err().to_string(),
+            "Refusing to remove 2 packages, more than the max removals threshold of 1. Pass --force to proceed"
+        );
+        assert!(check_removals(&old, &new, Some(2)).is_ok());
+        assert!(check_removals(&old, &new, None).is_ok());
+    }
+
+    #[test]
+    fn rollback_follows_the_recorded_parent() {
+        let cache = env::temp_dir().join(format!("dpmm-parent-{}", std::process::id()));
+        fs::create_dir_all(&cache).unwrap();
+        // 7 was switched from 2 after a rollback, 5 from 2 before it
+        for (n, parent) in [(0, None), (2, Some(0)), (5, Some(2)), (7, Some(2))] {
+            let generation = Generation {
+                parent,
+                ..Generation::new(vec![])
+            };
+            let t = toml::to_string(&generation).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let latest = read_generation(&cache, "generation_", "7").unwrap();
+        let target = previous_generation(&cache, "generation_", &latest);
+        let unrecorded = previous_generation(&cache, "generation_", &Generation::default());
+        fs::remove_dir_all(&cache).unwrap();
+        assert_eq!(target.unwrap(), 2);
+        // without a parent, the one before the latest
+        assert_eq!(unrecorded.unwrap(), 5);
+    }
+
+    #[test]
+    fn dry_run_leaves_a_fresh_cache_alone() {
+        let dir = env::temp_dir().join(format!("dpmm-dry-run-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = [\"apt\"]\n").unwrap();
+        let apt = "install = \"true $\"\nupdate = \"true\"\npackages = [\"git\"]\n";
+        fs::write(config.join("apt.toml"), apt).unwrap();
+        let results: Vec<_> = [&["switch"][..], &["update", "all"], &["list"]]
+            .into_iter()
+            .map(|command| {
+                let argv = ["dpmm", "--dry-run"].iter().chain(command);
+                run_in(Args::parse_from(argv), config.clone(), cache.clone())
+            })
+            .collect();
+        let created = cache.exists();
+        fs::remove_dir_all(&dir).unwrap();
+        for result in results {
+            result.unwrap();
+        }
+        assert!(!created);
+    }
+
+    #[test]
+    fn color_choice_controls_escapes() {
+        let old = Generation {
+            managers: vec![manager("apt", &["git", "fd"])],
+            ..Default::default()
+        };
+        let new = Generation {
+            managers: vec![manager("apt", &["git", "bat"])],
+            ..Default::default()
+        };
+        let diff = |choice: &str| {
+            let args = Args::parse_from(["dpmm", "--color", choice, "diff"]);
+            diff_lines(&old, &new, args.color.enabled()).join("\n")
+        };
+        assert_eq!(diff("never"), "apt:\n+ bat\n- fd");
+        // test output is piped, yet always colors
+        assert_eq!(
+            diff("always"),
+            "apt:\n\x1b[32m+ bat\x1b[0m\n\x1b[31m- fd\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn migrate_is_idempotent() {
+        let dir = env::temp_dir().join(format!("dpmm-migrate-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        fs::create_dir_all(&config).unwrap();
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = [\"apt\"]\n").unwrap();
+        fs::write(
+            config.join("apt.toml"),
+            "install = \"true $\"\npackages = []\n",
+        )
+        .unwrap();
+        // written before generations recorded anything but their managers
+        for n in [0, 1, 3] {
+            let old = "[[managers]]\nname = \"apt\"\ninstall = \"true $\"\npackages = [\"git\"]\n";
+            fs::write(cache.join(format!("generation_{n}.toml")), old).unwrap();
+        }
+        let snapshot = || {
+            [0, 1, 3]
+                .map(|n| fs::read_to_string(cache.join(format!("generation_{n}.toml"))).unwrap())
+        };
+        let migrate = || {
+            run_in(
+                Args::parse_from(["dpmm", "migrate"]),
+                config.clone(),
+                cache.clone(),
+            )
+        };
+        migrate().unwrap();
+        let once = snapshot();
+        migrate().unwrap();
+        let twice = snapshot();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(once, twice);
+        let generation: Generation = toml::from_str(&once[2]).unwrap();
+        assert_eq!(generation.parent, Some(1));
+        assert_eq!(generation.dpm_version.as_deref(), Some("unknown"));
+        assert!(generation.created_at.is_some());
+    }
+
+    #[test]
+    fn prune_before_keeps_the_baseline() {
+        let dir = env::temp_dir().join(format!("dpmm-prune-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        fs::create_dir_all(&config).unwrap();
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = []\n").unwrap();
+        for n in 0..=10u32 {
+            let generation = Generation {
+                parent: n.checked_sub(1),
+                ..Generation::new(vec![])
+            };
+            let t = toml::to_string(&generation).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let args = Args::parse_from(["dpmm", "prune", "--before", "5"]);
+        let result = run_in(args, config, cache.clone());
+        let numbers = generation_numbers(&cache, "generation_");
+        let fifth = read_generation(&cache, "generation_", "5");
+        fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+        assert_eq!(numbers.unwrap(), [10, 9, 8, 7, 6, 5, 0]);
+        assert_eq!(fifth.unwrap().parent, Some(0));
+    }
+
+    #[test]
+    fn upgrade_skips_managers_without_the_command() {
+        let managers = [manager("cargo", &["ripgrep"])];
+        let selected = HashSet::from(["cargo".to_string()]);
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let timings = run_maintenance(
+            &managers,
+            &selected,
+            "cargo",
+            &[],
+            "Upgrades",
+            |d| d.upgrade.as_ref(),
+            &opts,
+        );
+        assert!(timings.unwrap().is_empty());
+        assert_eq!(
+            no_command_note("cargo", "upgrade"),
+            "Manager 'cargo' has no upgrade command defined; skipping"
+        );
+    }
+
+    #[test]
+    fn new_manager_writes_a_valid_skeleton() {
+        let config = env::temp_dir().join(format!("dpmm-new-manager-{}", std::process::id()));
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = [\"apt\"]\n").unwrap();
+        let created = new_manager(&config, "cargo", false, false);
+        let again = new_manager(&config, "cargo", false, false);
+        let skeleton = fs::read_to_string(config.join("cargo.toml")).unwrap();
+        let dpmm = load_dpmm(&config);
+        fs::remove_dir_all(&config).unwrap();
+        created.unwrap();
+        assert!(again.is_err());
+        let cargo: Dpm = toml::from_str(&skeleton).unwrap();
+        assert!(cargo.install.has_placeholder() && cargo.packages.is_empty());
+        assert!(cargo.uninstall.is_some_and(|u| u.has_placeholder()));
+        assert_eq!(dpmm.unwrap().names().collect::<Vec<_>>(), ["apt", "cargo"]);
+    }
+
+    #[test]
+    fn squash_reparents_like_prune() {
+        let dir = env::temp_dir().join(format!("dpmm-squash-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        fs::create_dir_all(&config).unwrap();
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = []\n").unwrap();
+        // 7 was switched from 4 after rolling back to it
+        let parents = [(0, None), (1, Some(0)), (2, Some(1)), (3, Some(2))];
+        let parents =
+            parents
+                .into_iter()
+                .chain([(4, Some(3)), (5, Some(4)), (6, Some(5)), (7, Some(4))]);
+        for (n, parent) in parents {
+            let generation = Generation {
+                parent,
+                ..Generation::new(vec![])
+            };
+            let t = toml::to_string(&generation).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let args = Args::parse_from(["dpmm", "squash", "3", "5"]);
+        let result = run_in(args, config, cache.clone());
+        let numbers = generation_numbers(&cache, "generation_");
+        let parent =
+            |n: u32| read_generation(&cache, "generation_", &n.to_string()).map(|g| g.parent);
+        let parents = [5, 6, 7].map(parent);
+        fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+        assert_eq!(numbers.unwrap(), [7, 6, 5, 2, 1, 0]);
+        assert_eq!(parents.map(Result::unwrap), [Some(2), Some(5), Some(2)]);
+    }
+
+    #[test]
+    fn rollback_backs_up_the_config_for_undo() {
+        let dir = env::temp_dir().join(format!("dpmm-undo-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        fs::create_dir_all(&config).unwrap();
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = [\"apt\"]\n").unwrap();
+        let apt = "# system packages\ninstall = \"true $\"\nuninstall = \"true $\"\npackages = [\"git\", \"fd\"]\n";
+        fs::write(config.join("apt.toml"), apt).unwrap();
+        for (n, packages) in [(0, &[][..]), (1, &["git"]), (2, &["git", "fd"])] {
+            let mut apt = manager("apt", packages);
+            apt.uninstall = Some("true $".into());
+            let t = toml::to_string(&Generation::new(vec![apt])).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let run = |argv: &[&str]| run_in(Args::parse_from(argv), config.clone(), cache.clone());
+        let rolled_back = run(&["dpmm", "--yes", "rollback", "1"]);
+        let rewritten = fs::read_to_string(config.join("apt.toml")).unwrap();
+        let backup = fs::read_to_string(cache.join(ROLLBACK_BACKUP).join("apt.toml"));
+        let undone = run(&["dpmm", "rollback", "--undo"]);
+        let restored = fs::read_to_string(config.join("apt.toml")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        rolled_back.unwrap();
+        undone.unwrap();
+        let rewritten: Dpm = toml::from_str(&rewritten).unwrap();
+        assert_eq!(package_specs(&rewritten.packages), ["git"]);
+        assert_eq!(backup.unwrap(), apt);
+        assert_eq!(restored, apt);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dpmm_options_disable_managers() {
+        let dir = env::temp_dir().join(format!("dpmm-map-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        let log = dir.join("log");
+        fs::create_dir_all(&config).unwrap();
+        let dpmm = "[managers.apt]\nenabled = false\n\n[managers.npm]\n";
+        fs::write(config.join("dpmm.toml"), dpmm).unwrap();
+        fs::write(config.join("apt.toml"), logging_manager(&log, &["git"])).unwrap();
+        fs::write(config.join("npm.toml"), logging_manager(&log, &["pnpm"])).unwrap();
+        let result = run_in(Args::parse_from(["dpmm", "switch"]), config, cache);
+        let logged = fs::read_to_string(&log);
+        fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+        assert_eq!(logged.unwrap(), "npm pnpm\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timings_attribute_time_to_managers() {
+        let sleeping = |name: &str, seconds: &str| {
+            Dpm::builder()
+                .name(name)
+                .install(strings(&[
+                    "sh",
+                    "-c",
+                    &format!("sleep {seconds}"),
+                    "sh",
+                    "$",
+                ]))
+                .packages(["a"])
+                .build()
+        };
+        let new = Generation {
+            managers: vec![sleeping("apt", "0"), sleeping("cargo", "0.3")],
+            ..Default::default()
+        };
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let summary = apply_generation(&Generation::default(), &new, &opts).unwrap();
+        let timings = summary
+            .managers
+            .iter()
+            .map(|m| (m.name.clone(), m.elapsed))
+            .collect();
+        let lines = timing_lines(timings);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("  cargo\t0.3"), "{lines:?}");
+        assert!(lines[1].starts_with("  apt\t0.0"), "{lines:?}");
+        assert!(lines[2].starts_with("  total\t0.3"), "{lines:?}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn update_all_follows_after_ordering() {
+        let log = env::temp_dir().join(format!("dpmm-update-order-{}", std::process::id()));
+        let updating = |name: &str| {
+            let script = format!("echo {name} >> {}", log.display());
+            Dpm::builder()
+                .name(name)
+                .install("true $")
+                .update(strings(&["sh", "-c", &script]))
+        };
+        let managers = [
+            updating("cargo").after(["apt"]).build(),
+            updating("apt").build(),
+        ];
+        let selected: HashSet<String> = managers.iter().filter_map(|m| m.name.clone()).collect();
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let result = run_maintenance(
+            &managers,
+            &selected,
+            "all",
+            &[],
+            "Updates",
+            |d| d.update.as_ref(),
+            &opts,
+        );
+        let logged = fs::read_to_string(&log);
+        let _ = fs::remove_file(&log);
+        result.unwrap();
+        assert_eq!(logged.unwrap(), "apt\ncargo\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn upgrade_all_continues_past_failures() {
+        let log = env::temp_dir().join(format!("dpmm-upgrade-fail-{}", std::process::id()));
+        let upgrading = |name: &str, script: String| {
+            Dpm::builder()
+                .name(name)
+                .install("true $")
+                .upgrade(strings(&["sh", "-c", &script]))
+                .build()
+        };
+        let logging = |name: &str| format!("echo {name} >> {}", log.display());
+        let managers = [
+            upgrading("apt", logging("apt")),
+            upgrading("npm", "exit 1".to_string()),
+            upgrading("pip", logging("pip")),
+        ];
+        let selected: HashSet<String> = managers.iter().filter_map(|m| m.name.clone()).collect();
+        let run = |continue_on_error| {
+            let opts = RunOptions {
+                quiet: true,
+                continue_on_error,
+                ..Default::default()
+            };
+            let result = run_maintenance(
+                &managers,
+                &selected,
+                "all",
+                &[],
+                "Upgrades",
+                |d| d.upgrade.as_ref(),
+                &opts,
+            );
+            let logged = fs::read_to_string(&log).unwrap_or_default();
+            let _ = fs::remove_file(&log);
+            (result, logged)
+        };
+        let (result, logged) = run(true);
+        assert_eq!(result.unwrap_err().to_string(), "1 of 3 managers failed");
+        assert_eq!(logged, "apt\npip\n");
+        let (result, logged) = run(false);
+        assert!(result.unwrap_err().to_string().starts_with("npm"));
+        assert_eq!(logged, "apt\n");
+    }
+
+    #[test]
+    fn select_picks_the_chosen_generation() {
+        let cache = env::temp_dir().join(format!("dpmm-select-{}", std::process::id()));
+        fs::create_dir_all(&cache).unwrap();
+        for n in 0..4 {
+            let t = toml::to_string(&Generation::new(vec![])).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let pick = |answer: &str| pick_generation(&cache, "generation_", &mut answer.as_bytes());
+        let (second, invalid) = (pick("2\n"), pick("9\n"));
+        fs::remove_dir_all(&cache).unwrap();
+        // the latest generation isn't offered, so 2 is the one before the previous
+        assert_eq!(second.unwrap(), 1);
+        assert_eq!(invalid.unwrap_err().to_string(), "Invalid selection 9");
+    }
+
+    #[test]
+    fn repair_recreates_a_missing_baseline() {
+        let dir = env::temp_dir().join(format!("dpmm-repair-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        fs::create_dir_all(&config).unwrap();
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = []\n").unwrap();
+        let t = toml::to_string(&Generation::new(vec![])).unwrap();
+        fs::write(cache.join("generation_1.toml"), t).unwrap();
+        let warning = missing_baseline_warning(&cache, "generation_");
+        let args = Args::parse_from(["dpmm", "--repair", "list"]);
+        let result = run_in(args, config, cache.clone());
+        let (gen0, after) = (
+            cache.join("generation_0.toml").exists(),
+            missing_baseline_warning(&cache, "generation_"),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+        assert_eq!(
+            warning.unwrap(),
+            "Warning: the generation_0.toml baseline is missing, pass --repair to recreate it empty"
+        );
+        assert!(gen0);
+        assert_eq!(after, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reconcile_records_the_installed_packages() {
+        let dir = env::temp_dir().join(format!("dpmm-reconcile-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        let log = dir.join("log");
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = [\"apt\"]\n").unwrap();
+        let apt =
+            logging_manager(&log, &["git", "fd"]) + "list_installed = \"printf %s\\\\n git bat\"\n";
+        fs::write(config.join("apt.toml"), apt).unwrap();
+        let run = |command| {
+            run_in(
+                Args::parse_from(["dpmm", command]),
+                config.clone(),
+                cache.clone(),
+            )
+        };
+        let switched = run("switch");
+        fs::remove_file(&log).unwrap();
+        let reconciled = run("reconcile");
+        let (logged, latest) = (
+            log.exists(),
+            load_generation(&cache.join("generation_2.toml")),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        switched.unwrap();
+        reconciled.unwrap();
+        assert!(!logged);
+        let latest = latest.unwrap();
+        assert_eq!(latest.label.as_deref(), Some("reconcile"));
+        assert_eq!(package_specs(&latest.managers[0].packages), ["git", "bat"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn upgrade_passes_targeted_packages() {
+        let log = env::temp_dir().join(format!("dpmm-targeted-{}", std::process::id()));
+        let script = format!("echo \"$@\" >> {}", log.display());
+        let blanket = format!("echo all >> {}", log.display());
+        let upgrading = |name: &str, upgrade: CommandTemplate| {
+            Dpm::builder()
+                .name(name)
+                .install("true $")
+                .upgrade(upgrade)
+                .build()
+        };
+        let managers = [
+            upgrading(
+                "cargo",
+                strings(&["sh", "-c", &script, "sh", "{packages}"]).into(),
+            ),
+            upgrading("apt", strings(&["sh", "-c", &blanket]).into()),
+        ];
+        let selected: HashSet<String> = managers.iter().filter_map(|m| m.name.clone()).collect();
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let upgrade = |target: &str, packages: &[&str]| {
+            run_maintenance(
+                &managers,
+                &selected,
+                target,
+                &strings(packages),
+                "Upgrades",
+                |d| d.upgrade.as_ref(),
+                &opts,
+            )
+        };
+        let targeted = upgrade("cargo", &["ripgrep", "fd-find"]);
+        let blanket = upgrade("apt", &[]);
+        let unfilled = upgrade("cargo", &[]);
+        let unplaced = upgrade("apt", &["git"]);
+        let logged = fs::read_to_string(&log);
+        let _ = fs::remove_file(&log);
+        targeted.unwrap();
+        blanket.unwrap();
+        assert_eq!(logged.unwrap(), "ripgrep fd-find\nall\n");
+        assert_eq!(
+            unfilled.unwrap_err().to_string(),
+            "cargo's upgrade command has a package placeholder, pass the packages to upgrade"
+        );
+        assert_eq!(
+            unplaced.unwrap_err().to_string(),
+            "apt's upgrade command has no placeholder to pass packages to"
+        );
+    }
+
+    #[test]
+    fn clean_keeps_unrelated_files() {
+        let dir = env::temp_dir().join(format!("dpmm-clean-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        fs::create_dir_all(&config).unwrap();
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = []\n").unwrap();
+        let t = toml::to_string(&Generation::new(vec![])).unwrap();
+        for file in [
+            "generation_0.toml",
+            "generation_1.toml",
+            "generation_2.toml.tmp",
+        ] {
+            fs::write(cache.join(file), &t).unwrap();
+        }
+        fs::write(cache.join("notes.txt"), "mine\n").unwrap();
+        let result = run_in(Args::parse_from(["dpmm", "clean"]), config, cache.clone());
+        let mut files: Vec<_> = fs::read_dir(&cache)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        files.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+        assert_eq!(files, ["generation_0.toml", "notes.txt"]);
+    }
+
+    #[test]
+    fn history_tallies_changes_against_parents() {
+        let cache = env::temp_dir().join(format!("dpmm-history-{}", std::process::id()));
+        fs::create_dir_all(&cache).unwrap();
+        let write = |n: u32, parent: Option<u32>, packages: &[&str]| {
+            let generation = Generation {
+                parent,
+                ..Generation::new(vec![manager("apt", packages)])
+            };
+            let t = toml::to_string(&generation).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        };
+        write(0, None, &[]);
+        write(1, Some(0), &["git", "fd"]);
+        write(2, Some(1), &["git", "bat", "jq"]);
+        // rolled back to 1
+        write(3, Some(1), &["git"]);
+        let all = history(&cache, "generation_", usize::MAX);
+        let limited = history(&cache, "generation_", 2);
+        fs::remove_dir_all(&cache).unwrap();
+        let tallies = |h: Vec<HistoryEntry>| -> Vec<_> {
+            h.into_iter()
+                .map(|e| (e.number, e.parent, e.added, e.removed))
+                .collect()
+        };
+        assert_eq!(
+            tallies(all.unwrap()),
+            [
+                (3, Some(1), 0, 1),
+                (2, Some(1), 2, 1),
+                (1, Some(0), 2, 0),
+                (0, None, 0, 0)
+            ]
+        );
+        assert_eq!(tallies(limited.unwrap()).len(), 2);
+    }
+
+    #[test]
+    fn path_points_at_the_generation_file() {
+        let cache = env::temp_dir().join(format!("dpmm-path-{}", std::process::id()));
+        fs::create_dir_all(&cache).unwrap();
+        for n in 0..3 {
+            let t = toml::to_string(&Generation::new(vec![])).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let path = |generation| generation_path(&cache, "generation_", generation);
+        let (latest, first, missing) = (path(None), path(Some("1")), path(Some("7")));
+        fs::remove_dir_all(&cache).unwrap();
+        assert_eq!(latest.unwrap(), cache.join("generation_2.toml"));
+        assert_eq!(first.unwrap(), cache.join("generation_1.toml"));
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn diff_only_on_change_summarizes_in_one_line() {
+        let apt = |packages| Generation {
+            managers: vec![manager("apt", packages)],
+            ..Default::default()
+        };
+        let opts = RunOptions {
+            quiet: true,
+            dry_run: true,
+            ..Default::default()
+        };
+        let (old, new) = (apt(&["git", "fd"]), apt(&["git", "bat", "jq"]));
+        let unchanged = apply_generation(&old, &old, &opts).unwrap();
+        assert!(!unchanged.changed());
+        let changed = apply_generation(&old, &new, &opts).unwrap();
+        assert!(changed.changed());
+        assert_eq!(
+            switch_summary("generation_2", &changed, OutputFormat::Text),
+            "generation_2: +2 -1 (apt +2 -1)"
+        );
+        let json = switch_summary("generation_2", &changed, OutputFormat::Json);
+        assert!(!json.contains('\n'));
+        assert!(
+            json.starts_with(r#"{"generation":"generation_2","added":2,"removed":1,"#),
+            "{json}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn update_accepts_ignored_exit_codes() {
+        let updating = |ignored: &[i32]| {
+            Dpm::builder()
+                .name("apt")
+                .install("true $")
+                .update(strings(&["sh", "-c", "exit 100"]))
+                .ignore_exit_codes(ignored.iter().copied())
+                .build()
+        };
+        let selected = HashSet::from(["apt".to_string()]);
+        let opts = RunOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let update = |apt: Dpm| {
+            run_maintenance(
+                &[apt],
+                &selected,
+                "apt",
+                &[],
+                "Updates",
+                |d| d.update.as_ref(),
+                &opts,
+            )
+        };
+        update(updating(&[100])).unwrap();
+        assert!(update(updating(&[1])).is_err());
+    }
+
+    #[test]
+    fn prune_duplicates_keeps_the_oldest() {
+        let dir = env::temp_dir().join(format!("dpmm-duplicates-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        fs::create_dir_all(&config).unwrap();
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = []\n").unwrap();
+        let packages: [&[&str]; 4] = [&[], &["git"], &["git"], &["git", "fd"]];
+        for (n, packages) in (0u32..).zip(packages) {
+            let generation = Generation {
+                parent: n.checked_sub(1),
+                ..Generation::new(vec![manager("apt", packages)])
+            };
+            let t = toml::to_string(&generation).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let prune = |args: &[&str]| {
+            let args = Args::parse_from(["dpmm"].iter().chain(args));
+            run_in(args, config.clone(), cache.clone())
+        };
+        let dry_run = prune(&["--dry-run", "prune", "--duplicates"]);
+        let kept = generation_numbers(&cache, "generation_");
+        let pruned = prune(&["prune", "--duplicates"]);
+        let numbers = generation_numbers(&cache, "generation_");
+        let third = read_generation(&cache, "generation_", "3");
+        fs::remove_dir_all(&dir).unwrap();
+        dry_run.unwrap();
+        pruned.unwrap();
+        assert_eq!(kept.unwrap(), [3, 2, 1, 0]);
+        assert_eq!(numbers.unwrap(), [3, 1, 0]);
+        assert_eq!(third.unwrap().parent, Some(1));
+    }
+
+    #[test]
+    fn list_aligns_and_selects_columns() {
+        let cache = env::temp_dir().join(format!("dpmm-columns-{}", std::process::id()));
+        fs::create_dir_all(&cache).unwrap();
+        for n in [0, 9, 10] {
+            let generation = Generation {
+                label: (n == 9).then(|| "laptop".to_string()),
+                ..Generation::new(vec![])
+            };
+            let t = toml::to_string(&generation).unwrap();
+            fs::write(cache.join(format!("generation_{n}.toml")), t).unwrap();
+        }
+        let rows = |columns: &[ListColumn]| list_rows(&cache, "generation_", false, columns);
+        let (both, names) = (
+            rows(&[ListColumn::Name, ListColumn::Label]),
+            rows(&[ListColumn::Name]),
+        );
+        fs::remove_dir_all(&cache).unwrap();
+        let both = both.unwrap();
+        assert_eq!(
+            list_lines(&both, None),
+            [
+                "generation_10",
+                "generation_9   laptop",
+                "generation_0   (baseline)"
+            ]
+        );
+        assert_eq!(
+            list_lines(&both, Some(",")),
+            [
+                "generation_10,",
+                "generation_9,laptop",
+                "generation_0,(baseline)"
+            ]
+        );
+        assert_eq!(
+            list_lines(&names.unwrap(), None),
+            ["generation_10", "generation_9", "generation_0"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn config_paths_expand_home() {
+        let dir = env::temp_dir().join(format!("dpmm-config-paths-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        let log = dir.join("log");
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = [\"apt\", \"npm\"]\n").unwrap();
+        let script = format!("pwd >> {}", log.display());
+        let with_cwd = |cwd: &str| {
+            format!(
+                "install = [\"sh\", \"-c\", {script:?}, \"sh\", \"{{packages}}\"]\ncwd = {cwd:?}\npackages = [\"git\"]\n"
+            )
+        };
+        fs::write(config.join("apt.toml"), with_cwd("~")).unwrap();
+        fs::write(config.join("npm.toml"), with_cwd("${HOME}")).unwrap();
+        let checked = check_config(&config);
+        let result = run_in(Args::parse_from(["dpmm", "switch"]), config, cache);
+        let logged = fs::read_to_string(&log);
+        fs::remove_dir_all(&dir).unwrap();
+        checked.unwrap();
+        result.unwrap();
+        let home = fs::canonicalize(env::var("HOME").unwrap()).unwrap();
+        let dirs: Vec<PathBuf> = logged
+            .unwrap()
+            .lines()
+            .map(|l| fs::canonicalize(l).unwrap())
+            .collect();
+        assert_eq!(dirs, [home.clone(), home]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn disabled_managers_keep_their_state() {
+        let dir = env::temp_dir().join(format!("dpmm-disabled-{}", std::process::id()));
+        let (config, cache) = (dir.join("config"), dir.join("cache"));
+        let log = dir.join("log");
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join("dpmm.toml"), "managers = [\"apt\", \"npm\"]\n").unwrap();
+        let uninstall = format!(
+            "uninstall = [\"sh\", \"-c\", \"echo \\\"rm $@\\\" >> {}\", \"sh\", \"{{packages}}\"]\n",
+            log.display()
+        );
+        let apt = |packages: &[&str], enabled: bool| {
+            let apt =
+                logging_manager(&log, packages).replace("uninstall = \"true $\"\n", &uninstall);
+            fs::write(
+                config.join("apt.toml"),
+                format!("{apt}enabled = {enabled}\n"),
+            )
+            .unwrap();
+        };
+        let switch = || {
+            run_in(
+                Args::parse_from(["dpmm", "switch"]),
+                config.clone(),
+                cache.clone(),
+            )
+        };
+        fs::write(config.join("npm.toml"), logging_manager(&log, &["pnpm"])).unwrap();
+        apt(&["git"], true);
+        let first = switch();
+        // disabled with a package dropped, and re-enabled as it was
+        apt(&[], false);
+        let disabled = switch();
+        apt(&["git"], true);
+        let enabled = switch();
+        let logged = fs::read_to_string(&log);
+        fs::remove_dir_all(&dir).unwrap();
+        first.unwrap();
+        disabled.unwrap();
+        enabled.unwrap();
+        assert_eq!(logged.unwrap(), "apt git\nnpm pnpm\n");
+    }
+
+    #[test]
+    fn rollback_keeps_vars_and_rejects_unknown_ones() {
+        let written = manager("pip", &["{vars.tool}", "black"]);
+        let recorded = manager("pip", &["ruff", "requests"]);
+        let vars = BTreeMap::from([("tool".to_string(), "ruff".to_string())]);
+        let rolled_back = roll_back_packages(written.clone(), &recorded, None, &vars).unwrap();
+        assert_eq!(
+            package_specs(&rolled_back.packages),
+            ["{vars.tool}", "requests"]
+        );
+        assert_eq!(
+            roll_back_packages(written, &recorded, None, &BTreeMap::new()).unwrap_err(),
+            "unknown variable vars.tool"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Built-in commands of common package managers, used by manager files setting `preset`.
+
+/// The commands a preset fills in for a manager that doesn't set them itself.
+pub struct Preset {
+    pub name: &'static str,
+    pub update: Option<&'static str>,
+    pub upgrade: &'static str,
+    pub install: &'static str,
+    pub uninstall: &'static str,
+    pub list_installed: Option<&'static str>,
+    pub add_repo: Option<&'static str>,
+    pub remove_repo: Option<&'static str>,
+    pub supports_multi_args: bool,
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "apt",
+        update: Some("sudo apt-get update"),
+        upgrade: "sudo apt-get upgrade -y",
+        install: "sudo apt-get install -y $=$version",
+        uninstall: "sudo apt-get remove -y $",
+        list_installed: Some("apt-mark showmanual"),
+        add_repo: Some("sudo add-apt-repository -y $"),
+        remove_repo: Some("sudo add-apt-repository -r -y $"),
+        supports_multi_args: true,
+    },
+    Preset {
+        name: "dnf",
+        update: Some("sudo dnf makecache"),
+        upgrade: "sudo dnf upgrade -y",
+        install: "sudo dnf install -y $",
+        uninstall: "sudo dnf remove -y $",
+        list_installed: Some("dnf repoquery --userinstalled --qf %{name}"),
+        add_repo: None,
+        remove_repo: None,
+        supports_multi_args: true,
+    },
+    Preset {
+        name: "pacman",
+        update: Some("sudo pacman -Sy"),
+        upgrade: "sudo pacman -Syu --noconfirm",
+        install: "sudo pacman -S --needed --noconfirm $",
+        uninstall: "sudo pacman -Rns --noconfirm $",
+        list_installed: Some("pacman -Qqe"),
+        add_repo: None,
+        remove_repo: None,
+        supports_multi_args: true,
+    },
+    Preset {
+        name: "brew",
+        update: Some("brew update"),
+        upgrade: "brew upgrade",
+        install: "brew install $",
+        uninstall: "brew uninstall $",
+        list_installed: Some("brew leaves"),
+        add_repo: Some("brew tap $"),
+        remove_repo: Some("brew untap $"),
+        supports_multi_args: true,
+    },
+    Preset {
+        name: "cargo",
+        update: None,
+        upgrade: "cargo install $",
+        install: "cargo install $ --version=$version",
+        uninstall: "cargo uninstall $",
+        list_installed: None,
+        add_repo: None,
+        remove_repo: None,
+        supports_multi_args: true,
+    },
+    Preset {
+        name: "pip",
+        update: None,
+        upgrade: "pip install --upgrade $",
+        install: "pip install $==$version",
+        uninstall: "pip uninstall -y $",
+        list_installed: None,
+        add_repo: None,
+        remove_repo: None,
+        supports_multi_args: true,
+    },
+    Preset {
+        name: "npm",
+        update: None,
+        upgrade: "npm update -g",
+        install: "npm install -g $",
+        uninstall: "npm uninstall -g $",
+        list_installed: None,
+        add_repo: None,
+        remove_repo: None,
+        supports_multi_args: true,
+    },
+    Preset {
+        name: "flatpak",
+        update: Some("flatpak update --appstream -y"),
+        upgrade: "flatpak update -y",
+        install: "flatpak install -y $",
+        uninstall: "flatpak uninstall -y $",
+        list_installed: Some("flatpak list --app --columns=application"),
+        add_repo: Some("flatpak remote-add --if-not-exists $"),
+        remove_repo: Some("flatpak remote-delete $"),
+        supports_multi_args: true,
+    },
+    Preset {
+        name: "snap",
+        update: None,
+        upgrade: "sudo snap refresh",
+        install: "sudo snap install $",
+        uninstall: "sudo snap remove $",
+        list_installed: None,
+        add_repo: None,
+        remove_repo: None,
+        supports_multi_args: true,
+    },
+    Preset {
+        name: "winget",
+        update: Some("winget source update"),
+        upgrade: "winget upgrade --all",
+        install: "winget install -e --id $",
+        uninstall: "winget uninstall -e --id $",
+        list_installed: None,
+        add_repo: None,
+        remove_repo: None,
+        supports_multi_args: false,
+    },
+    Preset {
+        name: "scoop",
+        update: Some("scoop update"),
+        upgrade: "scoop update *",
+        install: "scoop install $",
+        uninstall: "scoop uninstall $",
+        list_installed: None,
+        add_repo: Some("scoop bucket add $"),
+        remove_repo: Some("scoop bucket rm $"),
+        supports_multi_args: true,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|p| p.name == name)
+}
@@ -0,0 +1,408 @@
+//! JSON Schemas for the config files, for editor completion and validation (e.g. with taplo).
+//! These are written by hand, so keep them in sync with [`crate::Dpmm`] and [`crate::Dpm`].
+
+pub const DPMM_SCHEMA: &str = r##"{
+  "$schema": "http://json-schema.org/draft-07/schema#",
+  "title": "dpmm.toml",
+  "type": "object",
+  "additionalProperties": false,
+  "properties": {
+    "schema_version": {
+      "description": "Format version of this file, see `dpmm migrate-config`. Unset means a config from before versioning",
+      "type": "integer",
+      "minimum": 0
+    },
+    "source": {
+      "description": "Git repository cloned into the cache directory and used as the config directory instead, the rest of this file being ignored. Refreshed with `dpmm sync`",
+      "type": "string"
+    },
+    "managers": {
+      "description": "Managers in the config directory, apt maps to apt.toml. These are handled sequentially",
+      "oneOf": [
+        {
+          "type": "array",
+          "items": {
+            "oneOf": [
+              { "type": "string" },
+              {
+                "type": "object",
+                "description": "A manager's name with the options overriding those of its file, or with an install command its whole definition, as in a manager file",
+                "required": ["name"],
+                "properties": { "name": { "type": "string" } }
+              }
+            ]
+          }
+        },
+        {
+          "type": "object",
+          "description": "Manager names mapped to options overriding those of their files, or with an install command to their whole definition",
+          "additionalProperties": { "type": "object" }
+        }
+      ]
+    },
+    "max_removals": {
+      "description": "Abort a switch that would remove more packages than this, unless --force is passed",
+      "type": "integer",
+      "minimum": 0
+    },
+    "env_file": {
+      "description": "Default env_file of the managers, relative to the config directory",
+      "type": "string"
+    },
+    "groups": {
+      "description": "Package groups of the managers that are used. Overridden by --group",
+      "type": "array",
+      "items": { "type": "string" }
+    },
+    "include": {
+      "description": "Files mapping manager names to packages added to theirs, relative to the config directory. File names may contain * and ? wildcards",
+      "type": "array",
+      "items": { "type": "string" }
+    },
+    "duplicate_packages": {
+      "description": "What switch and check do about a package declared by several managers. The default is warn",
+      "enum": ["allow", "warn", "error"]
+    },
+    "pre_switch": {
+      "description": "Command run in the config directory before a switch loads the config, e.g. `git pull`",
+      "oneOf": [
+        { "type": "string" },
+        { "type": "array", "items": { "type": "string" } }
+      ]
+    },
+    "post_switch": {
+      "description": "Command run in the config directory after a switch succeeded",
+      "oneOf": [
+        { "type": "string" },
+        { "type": "array", "items": { "type": "string" } }
+      ]
+    },
+    "vars": {
+      "description": "Values substituted for {vars.<name>} in the managers' commands and package names",
+      "type": "object",
+      "additionalProperties": { "type": "string" }
+    },
+    "profile": {
+      "description": "The profile used when --profile isn't passed",
+      "type": "string"
+    },
+    "profiles": {
+      "description": "Managers and groups used under --profile <name>, whose generations are kept apart",
+      "type": "object",
+      "additionalProperties": {
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+          "managers": {
+            "description": "Managers of this file that are used. Unset means all",
+            "type": "array",
+            "items": { "type": "string" }
+          },
+          "groups": {
+            "description": "Package groups used instead of the top-level ones. Overridden by --group",
+            "type": "array",
+            "items": { "type": "string" }
+          }
+        }
+      }
+    },
+    "host_prefix": {
+      "description": "Keep this machine's generations in their own generation_<prefix>_N series. Overridden by --host-prefix",
+      "type": "string"
+    }
+  }
+}
+"##;
+
+pub const MANAGER_SCHEMA: &str = r##"{
+  "$schema": "http://json-schema.org/draft-07/schema#",
+  "title": "dpmm manager",
+  "type": "object",
+  "additionalProperties": false,
+  "required": ["packages"],
+  "definitions": {
+    "one_or_many": {
+      "oneOf": [
+        { "type": "string" },
+        { "type": "array", "items": { "type": "string" } }
+      ]
+    },
+    "package": {
+      "description": "A package, optionally pinned as name@version, or a table with its name and version",
+      "oneOf": [
+        { "type": "string" },
+        {
+          "type": "object",
+          "additionalProperties": false,
+          "required": ["name"],
+          "properties": {
+            "name": { "type": "string" },
+            "version": { "type": "string" },
+            "os": {
+              "description": "Operating systems, as in Rust's std::env::consts::OS, the package is used on",
+              "$ref": "#/definitions/one_or_many"
+            },
+            "arch": {
+              "description": "CPU architectures, as in Rust's std::env::consts::ARCH, the package is used on",
+              "$ref": "#/definitions/one_or_many"
+            },
+            "only": {
+              "description": "Platforms the package is used on, each an OS, an architecture or both as os-arch, e.g. linux-x86_64",
+              "$ref": "#/definitions/one_or_many"
+            },
+            "exclude": {
+              "description": "Platforms, named like those of only, the package isn't used on",
+              "$ref": "#/definitions/one_or_many"
+            },
+            "args": {
+              "description": "Extra arguments appended to the install command of this package, which is then installed on its own",
+              "$ref": "#/definitions/command"
+            },
+            "scope": {
+              "description": "Scope the package is installed in, overriding the manager's",
+              "enum": ["user", "system"]
+            },
+            "optional": {
+              "description": "Whether a failed install only warns, the package being left unrecorded so the next switch tries again. The default is false",
+              "type": "boolean"
+            },
+            "reason": {
+              "description": "Why the package is installed, shown by dpmm why",
+              "type": "string"
+            },
+            "tags": {
+              "description": "Free-form labels, shown by dpmm why",
+              "$ref": "#/definitions/one_or_many"
+            },
+            "installed_as": {
+              "description": "The manager's name for the package, passed to its commands instead of name",
+              "type": "string"
+            }
+          }
+        }
+      ]
+    },
+    "overrides": {
+      "type": "object",
+      "additionalProperties": false,
+      "required": ["packages"],
+      "properties": {
+        "replace": {
+          "description": "Replace the base packages instead of extending them. The default is false",
+          "type": "boolean"
+        },
+        "packages": { "type": "array", "items": { "$ref": "#/definitions/package" } }
+      }
+    },
+    "command": {
+      "description": "A command, either a string split on whitespace or an explicit argv. `$` is replaced by the packages, `$version` by a pinned package's version and `${VAR}` by the environment variable. Commands using the named placeholders {packages}, {package}, {version} or {manager} take those instead, leaving `$` alone",
+      "oneOf": [
+        { "type": "string" },
+        { "type": "array", "items": { "type": "string" } }
+      ]
+    }
+  },
+  "properties": {
+    "name": {
+      "description": "The file's stem is used to identify the manager",
+      "type": "string"
+    },
+    "preset": {
+      "description": "Built-in commands of a common package manager, filling in update, upgrade, install, uninstall, list_installed, add_repo, remove_repo and supports_multi_args where they're unset",
+      "enum": ["apt", "dnf", "pacman", "brew", "cargo", "pip", "npm", "flatpak", "snap", "winget", "scoop"]
+    },
+    "extends": {
+      "description": "A manager file of the config directory, e.g. pip for pip.toml, or a preset, whose commands and options fill in those this file leaves unset. Packages, groups, hosts, profiles, repos, absent, after and enabled aren't inherited",
+      "type": "string"
+    },
+    "update": { "$ref": "#/definitions/command" },
+    "upgrade": { "$ref": "#/definitions/command" },
+    "install": {
+      "description": "Required unless a preset or the extended manager provides it",
+      "$ref": "#/definitions/command"
+    },
+    "uninstall": {
+      "description": "Unset for managers that can't remove packages, whose removals are skipped with a warning",
+      "$ref": "#/definitions/command"
+    },
+    "scope": {
+      "description": "Default scope of the packages, picking install_user/uninstall_user or install_system/uninstall_system",
+      "enum": ["user", "system"]
+    },
+    "install_user": {
+      "description": "Installs the packages of the user scope. Defaults to install",
+      "$ref": "#/definitions/command"
+    },
+    "install_system": {
+      "description": "Installs the packages of the system scope. Defaults to install",
+      "$ref": "#/definitions/command"
+    },
+    "uninstall_user": {
+      "description": "Uninstalls the packages of the user scope. Defaults to uninstall",
+      "$ref": "#/definitions/command"
+    },
+    "uninstall_system": {
+      "description": "Uninstalls the packages of the system scope. Defaults to uninstall",
+      "$ref": "#/definitions/command"
+    },
+    "pre_install": {
+      "description": "Run before a switch's installs of this manager, placeholders being replaced by the packages",
+      "$ref": "#/definitions/command"
+    },
+    "post_install": {
+      "description": "Run after a switch's installs of this manager succeeded",
+      "$ref": "#/definitions/command"
+    },
+    "pre_uninstall": {
+      "description": "Run before a switch's uninstalls of this manager, placeholders being replaced by the packages",
+      "$ref": "#/definitions/command"
+    },
+    "post_uninstall": {
+      "description": "Run after a switch's uninstalls of this manager succeeded",
+      "$ref": "#/definitions/command"
+    },
+    "add_repo": {
+      "description": "Adds one of repos, `$` being replaced by its whitespace-separated words",
+      "$ref": "#/definitions/command"
+    },
+    "remove_repo": {
+      "description": "Removes a repo dropped from repos",
+      "$ref": "#/definitions/command"
+    },
+    "supports_multi_args": {
+      "description": "Whether install/uninstall accept multiple packages at once. The default is true",
+      "type": "boolean"
+    },
+    "batch_size": {
+      "description": "With supports_multi_args, the most packages passed to a single install/uninstall. Unset passes them all at once",
+      "type": "integer",
+      "minimum": 1
+    },
+    "ignore_exit_codes": {
+      "description": "Exit codes of the install, uninstall, update and upgrade commands that are treated as success",
+      "type": "array",
+      "items": { "type": "integer" }
+    },
+    "is_installed": {
+      "description": "Query run per package before installing it, exiting 0 means it's already installed",
+      "type": "string"
+    },
+    "list_installed": {
+      "description": "Command printing the installed packages one per line, used by reconcile",
+      "type": "string"
+    },
+    "list_available": {
+      "description": "Command printing the available packages one per line, which packages with * or ? wildcards are expanded to",
+      "type": "string"
+    },
+    "version_check": {
+      "description": "Command printing the manager's version, the first version number in its output being compared with min_version",
+      "type": "string"
+    },
+    "min_version": {
+      "description": "Oldest version of the manager, per version_check, a switch runs its commands with",
+      "type": "string"
+    },
+    "env_file": {
+      "description": "File of KEY=VALUE lines set in the environment of this manager's commands, relative to the config directory. Values are never printed",
+      "type": "string"
+    },
+    "env": {
+      "description": "Variables set in the environment of this manager's commands, before those of env_file. Values are never printed",
+      "type": "object",
+      "additionalProperties": { "type": "string" }
+    },
+    "cwd": {
+      "description": "Working directory of this manager's commands, relative to the config directory",
+      "type": "string"
+    },
+    "include_in_all": {
+      "description": "Whether `update all`/`upgrade all` include this manager. The default is true",
+      "type": "boolean"
+    },
+    "confirm_removals": {
+      "description": "Ask before running this manager's uninstalls, when interactive. Skipped with --yes",
+      "type": "boolean"
+    },
+    "enabled": {
+      "description": "Whether switch, update and upgrade handle this manager. The default is true",
+      "type": "boolean"
+    },
+    "after": {
+      "description": "Managers whose changes must be applied before this one's",
+      "type": "array",
+      "items": { "type": "string" }
+    },
+    "os": {
+      "description": "Operating systems, as in Rust's std::env::consts::OS, this manager is used on. Unset means all",
+      "type": "array",
+      "items": { "type": "string" }
+    },
+    "packages_cmd": {
+      "description": "Command whose newline-separated stdout is appended to packages on every run",
+      "type": "string"
+    },
+    "include": {
+      "description": "Files whose `packages` are added to this manager's, relative to the config directory. File names may contain * and ? wildcards",
+      "type": "array",
+      "items": { "type": "string" }
+    },
+    "packages": {
+      "type": "array",
+      "items": { "$ref": "#/definitions/package" }
+    },
+    "repos": {
+      "description": "Repositories, taps or remotes the packages come from, added with add_repo before a switch's installs and removed with remove_repo after its uninstalls once dropped",
+      "type": "array",
+      "items": { "type": "string" }
+    },
+    "absent": {
+      "description": "Packages every switch uninstalls if they're installed, found with is_installed or list_installed",
+      "type": "array",
+      "items": { "type": "string" }
+    },
+    "groups": {
+      "description": "Named sets of packages, added to packages when selected with `groups` in dpmm.toml or --group",
+      "type": "object",
+      "additionalProperties": {
+        "type": "object",
+        "additionalProperties": false,
+        "required": ["packages"],
+        "properties": {
+          "packages": { "type": "array", "items": { "$ref": "#/definitions/package" } }
+        }
+      }
+    },
+    "hosts": {
+      "description": "Package overrides applied on the machine with the given hostname, or with --host <name>",
+      "type": "object",
+      "additionalProperties": { "$ref": "#/definitions/overrides" }
+    },
+    "profiles": {
+      "description": "Package overrides applied with --profile <name>",
+      "type": "object",
+      "additionalProperties": { "$ref": "#/definitions/overrides" }
+    }
+  }
+}
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::parse;
+
+    #[test]
+    fn schemas_are_json() {
+        let dpmm = parse(DPMM_SCHEMA).unwrap();
+        assert!(dpmm["properties"].get("managers").is_some());
+        let manager = parse(MANAGER_SCHEMA).unwrap();
+        assert_eq!(
+            manager["required"].as_array().unwrap(),
+            &[toml::Value::from("packages")]
+        );
+        for field in ["install", "uninstall", "packages"] {
+            assert!(manager["properties"].get(field).is_some(), "{field}");
+        }
+    }
+}
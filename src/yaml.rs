@@ -0,0 +1,569 @@
+//! A small YAML reader for YAML config files, producing the same values as their TOML
+//! equivalents so both deserialize alike. It covers what configs use: block mappings and
+//! sequences, flow `[...]` and `{...}` collections on a single line, quoted and plain
+//! scalars, `|` and `>` block scalars and comments. Anchors, tags and multiple documents
+//! aren't supported. As in JSON configs, `null` members are left out.
+
+use toml::{Table, Value};
+
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        lines: text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                let text = line.trim_start_matches(' ');
+                Line {
+                    indent: line.len() - text.len(),
+                    text: text.trim_end().to_string(),
+                    number: i + 1,
+                }
+            })
+            .collect(),
+        pos: 0,
+    };
+    parser.skip_blank();
+    if parser.content().is_some_and(|c| c == "---") {
+        parser.pos += 1;
+        parser.skip_blank();
+    }
+    let value = match parser.lines.get(parser.pos) {
+        Some(line) => parser.block(line.indent)?,
+        None => Some(Value::Table(Table::new())),
+    };
+    parser.skip_blank();
+    match parser.content() {
+        None => value.ok_or_else(|| "null isn't a valid config".to_string()),
+        Some("---") => Err(parser.error("only one document is supported")),
+        Some("...") => Ok(value.unwrap_or_else(|| Value::Table(Table::new()))),
+        Some(_) => Err(parser.error("unexpected indentation")),
+    }
+}
+
+struct Line {
+    indent: usize,
+    /// The line after its indentation, comments included
+    text: String,
+    number: usize,
+}
+
+struct Parser {
+    lines: Vec<Line>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> String {
+        match self.lines.get(self.pos) {
+            Some(line) => format!("{message} at line {}", line.number),
+            None => format!("{message} at the end of the file"),
+        }
+    }
+
+    /// The current line without its comment, `None` at the end.
+    fn content(&self) -> Option<&str> {
+        self.lines
+            .get(self.pos)
+            .map(|line| strip_comment(&line.text))
+    }
+
+    fn skip_blank(&mut self) {
+        while self.content().is_some_and(str::is_empty) {
+            self.pos += 1;
+        }
+    }
+
+    /// The node starting at the current line, indented by `indent`.
+    fn block(&mut self, indent: usize) -> Result<Option<Value>, String> {
+        if self.lines[self.pos].text.starts_with('\t') {
+            return Err(self.error("tabs aren't allowed in indentation"));
+        }
+        let content = self.content().unwrap_or_default();
+        if is_sequence_item(content) {
+            Ok(Some(Value::Array(self.sequence(indent)?)))
+        } else if split_key(content).is_some() {
+            Ok(Some(Value::Table(self.mapping(indent)?)))
+        } else {
+            let value = flow_value(content).map_err(|e| self.error(&e))?;
+            self.pos += 1;
+            Ok(value)
+        }
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<Vec<Value>, String> {
+        let mut values = vec![];
+        loop {
+            self.skip_blank();
+            let Some(line) = self.lines.get(self.pos) else {
+                return Ok(values);
+            };
+            if line.indent != indent || !is_sequence_item(strip_comment(&line.text)) {
+                return Ok(values);
+            }
+            let rest = line.text[1..].trim_start_matches(' ');
+            let value = if strip_comment(rest).is_empty() {
+                self.pos += 1;
+                self.skip_blank();
+                match self.lines.get(self.pos) {
+                    Some(line) if line.indent > indent => self.block(line.indent)?,
+                    _ => None,
+                }
+            } else {
+                // the item's node starts on the same line, as if it were on its own one
+                let offset = line.text.len() - rest.len();
+                let rest = rest.to_string();
+                let line = &mut self.lines[self.pos];
+                line.indent += offset;
+                line.text = rest;
+                self.block(indent + offset)?
+            };
+            match value {
+                Some(value) => values.push(value),
+                None => return Err(self.error("null isn't allowed in sequences")),
+            }
+        }
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<Table, String> {
+        let mut table = Table::new();
+        loop {
+            self.skip_blank();
+            let Some(line) = self.lines.get(self.pos) else {
+                return Ok(table);
+            };
+            if line.indent < indent {
+                return Ok(table);
+            }
+            if line.indent > indent {
+                return Err(self.error("unexpected indentation"));
+            }
+            let content = strip_comment(&line.text);
+            if content == "---" || content == "..." {
+                return Ok(table);
+            }
+            let Some((key, rest)) = split_key(content) else {
+                return Err(self.error("expected a `key: value` entry"));
+            };
+            let key = if key.starts_with(['"', '\'']) {
+                FlowParser::new(key).quoted().map_err(|e| self.error(&e))?
+            } else {
+                key.to_string()
+            };
+            let rest = rest.trim().to_string();
+            if table.contains_key(&key) {
+                return Err(self.error(&format!("duplicate key `{key}`")));
+            }
+            let value = if rest.is_empty() {
+                self.pos += 1;
+                self.nested(indent)?
+            } else if rest.starts_with(['|', '>']) {
+                self.pos += 1;
+                Some(Value::String(self.block_scalar(indent, &rest)?))
+            } else {
+                let value = flow_value(&rest).map_err(|e| self.error(&e))?;
+                self.pos += 1;
+                value
+            };
+            if let Some(value) = value {
+                table.insert(key, value);
+            }
+        }
+    }
+
+    /// The node on the lines after an entry of `indent` with no value of its own, `None` when
+    /// there's none. A sequence may be indented as much as its key.
+    fn nested(&mut self, indent: usize) -> Result<Option<Value>, String> {
+        self.skip_blank();
+        let Some(line) = self.lines.get(self.pos) else {
+            return Ok(None);
+        };
+        if line.indent > indent {
+            self.block(line.indent)
+        } else if line.indent == indent && is_sequence_item(strip_comment(&line.text)) {
+            Ok(Some(Value::Array(self.sequence(indent)?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The `|` literal or `>` folded scalar on the lines indented deeper than `indent`.
+    fn block_scalar(&mut self, indent: usize, header: &str) -> Result<String, String> {
+        let (folded, chomping) = header.split_at(1);
+        let folded = folded == ">";
+        if !matches!(chomping, "" | "-" | "+") {
+            return Err(self.error("unsupported block scalar header"));
+        }
+        let mut lines = vec![];
+        while let Some(line) = self.lines.get(self.pos)
+            && (line.text.is_empty() || line.indent > indent)
+        {
+            lines.push(line);
+            self.pos += 1;
+        }
+        let trailing = lines.iter().rev().take_while(|l| l.text.is_empty()).count();
+        lines.truncate(lines.len() - trailing);
+        let block_indent = lines.first().map_or(0, |l| l.indent);
+        let mut text = String::new();
+        for (i, line) in lines.iter().enumerate() {
+            if line.text.is_empty() {
+                text.push('\n');
+                continue;
+            }
+            if i > 0 && !text.ends_with('\n') {
+                text.push(if folded { ' ' } else { '\n' });
+            }
+            text.push_str(&" ".repeat(line.indent.saturating_sub(block_indent)));
+            text.push_str(&line.text);
+        }
+        match chomping {
+            "-" => (),
+            "+" => text.push_str(&"\n".repeat(trailing + 1)),
+            _ if !text.is_empty() => text.push('\n'),
+            _ => (),
+        }
+        Ok(text)
+    }
+}
+
+fn is_sequence_item(content: &str) -> bool {
+    content == "-" || content.starts_with("- ")
+}
+
+/// `text` up to its comment, a `#` at its start or after whitespace, outside of quotes.
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut previous = ' ';
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            // `''` is a quote in a single quoted string
+            Some('\'') if c == '\'' && text[i + 1..].starts_with('\'') => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '#' && previous.is_whitespace() => return text[..i].trim_end(),
+            None if (c == '"' || c == '\'')
+                && (previous.is_whitespace() || "[{:,".contains(previous)) =>
+            {
+                quote = Some(c)
+            }
+            None => (),
+        }
+        previous = c;
+    }
+    text.trim_end()
+}
+
+/// The key of a `key: value` entry, quotes included, and the text after its colon, `None`
+/// for other nodes.
+fn split_key(content: &str) -> Option<(&str, &str)> {
+    if content.starts_with(['"', '\'']) {
+        let mut parser = FlowParser::new(content);
+        parser.quoted().ok()?;
+        let (key, after) = content.split_at(parser.byte_pos());
+        let rest = after.trim_start().strip_prefix(':')?;
+        return (rest.is_empty() || rest.starts_with(' ')).then_some((key, rest));
+    }
+    if content.starts_with(['[', '{', '-']) && !content.starts_with("-:") {
+        return None;
+    }
+    let colon = content
+        .match_indices(':')
+        .map(|(i, _)| i)
+        .find(|&i| content[i + 1..].is_empty() || content[i + 1..].starts_with(' '))?;
+    Some((content[..colon].trim_end(), &content[colon + 1..]))
+}
+
+/// The value of a single line node, a scalar or a flow collection.
+fn flow_value(text: &str) -> Result<Option<Value>, String> {
+    let mut parser = FlowParser::new(text);
+    let value = parser.value(false)?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(format!("trailing characters after a {}", parser.what()));
+    }
+    Ok(value)
+}
+
+struct FlowParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl FlowParser {
+    fn new(text: &str) -> Self {
+        FlowParser {
+            chars: text.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn byte_pos(&self) -> usize {
+        self.chars[..self.pos].iter().map(|c| c.len_utf8()).sum()
+    }
+
+    fn what(&self) -> &'static str {
+        match self.chars.first() {
+            Some('[') => "flow sequence",
+            Some('{') => "flow mapping",
+            _ => "quoted string",
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.pos).copied()
+    }
+
+    /// The next value, `None` for null. In a flow collection, plain scalars end at `,`, `]`
+    /// and `}`.
+    fn value(&mut self, in_flow: bool) -> Result<Option<Value>, String> {
+        match self.peek() {
+            Some('[') => Ok(Some(Value::Array(self.sequence()?))),
+            Some('{') => Ok(Some(Value::Table(self.mapping()?))),
+            Some('"' | '\'') => Ok(Some(Value::String(self.quoted()?))),
+            Some('&' | '*' | '!') => Err("anchors, aliases and tags aren't supported".to_string()),
+            _ => Ok(plain_scalar(&self.plain(in_flow, false))),
+        }
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Value>, String> {
+        self.pos += 1;
+        let mut values = vec![];
+        loop {
+            match self.peek() {
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(values);
+                }
+                None => {
+                    return Err("unterminated flow sequence, it has to fit on one line".to_string());
+                }
+                Some(_) => (),
+            }
+            match self.value(true)? {
+                Some(value) => values.push(value),
+                None => return Err("null isn't allowed in sequences".to_string()),
+            }
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => (),
+                _ => return Err("expected `,` or `]` in a flow sequence".to_string()),
+            }
+        }
+    }
+
+    fn mapping(&mut self) -> Result<Table, String> {
+        self.pos += 1;
+        let mut table = Table::new();
+        loop {
+            let key = match self.peek() {
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(table);
+                }
+                Some('"' | '\'') => self.quoted()?,
+                Some(_) => self.plain(true, true),
+                None => {
+                    return Err("unterminated flow mapping, it has to fit on one line".to_string());
+                }
+            };
+            if self.peek() != Some(':') {
+                return Err(format!("expected `:` after `{key}` in a flow mapping"));
+            }
+            self.pos += 1;
+            if let Some(value) = self.value(true)? {
+                table.insert(key, value);
+            }
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => (),
+                _ => return Err("expected `,` or `}` in a flow mapping".to_string()),
+            }
+        }
+    }
+
+    /// A plain scalar, ending at a `:` too when it's a key.
+    fn plain(&mut self, in_flow: bool, key: bool) -> String {
+        let start = self.pos;
+        while let Some(&c) = self.chars.get(self.pos) {
+            let next = self.chars.get(self.pos + 1).copied();
+            if (in_flow && ",]}".contains(c))
+                || (key && c == ':' && next.is_none_or(|n| n.is_whitespace() || ",}".contains(n)))
+            {
+                break;
+            }
+            self.pos += 1;
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .collect::<String>()
+            .trim()
+            .to_string()
+    }
+
+    fn quoted(&mut self) -> Result<String, String> {
+        let quote = self.chars[self.pos];
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let Some(c) = self.chars.get(self.pos).copied() else {
+                return Err("unterminated string".to_string());
+            };
+            self.pos += 1;
+            match c {
+                '\'' if quote == '\'' => {
+                    if self.chars.get(self.pos) == Some(&'\'') {
+                        self.pos += 1;
+                        s.push('\'');
+                    } else {
+                        return Ok(s);
+                    }
+                }
+                '"' if quote == '"' => return Ok(s),
+                '\\' if quote == '"' => {
+                    let Some(escaped) = self.chars.get(self.pos).copied() else {
+                        return Err("unterminated string".to_string());
+                    };
+                    self.pos += 1;
+                    s.push(match escaped {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        '0' => '\0',
+                        'a' => '\u{7}',
+                        'b' => '\u{8}',
+                        'e' => '\u{1b}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        ' ' => ' ',
+                        'x' => self.hex(2)?,
+                        'u' => self.hex(4)?,
+                        'U' => self.hex(8)?,
+                        _ => return Err("invalid escape".to_string()),
+                    });
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn hex(&mut self, len: usize) -> Result<char, String> {
+        let end = self.pos + len;
+        let digits: String = self
+            .chars
+            .get(self.pos..end)
+            .unwrap_or_default()
+            .iter()
+            .collect();
+        self.pos = end;
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| "invalid escape".to_string())
+    }
+}
+
+/// The value of a plain scalar, as resolved by YAML's core schema. `None` for null.
+fn plain_scalar(text: &str) -> Option<Value> {
+    let value = match text {
+        "" | "~" | "null" | "Null" | "NULL" => return None,
+        "true" | "True" | "TRUE" => Value::Boolean(true),
+        "false" | "False" | "FALSE" => Value::Boolean(false),
+        _ => {
+            let number = text.strip_prefix('+').unwrap_or(text);
+            let numeric = number
+                .trim_start_matches('-')
+                .starts_with(|c: char| c.is_ascii_digit() || c == '.');
+            if let Ok(n) = number.parse::<i64>() {
+                Value::Integer(n)
+            } else if numeric && let Ok(n) = number.parse::<f64>() {
+                Value::Float(n)
+            } else {
+                Value::String(text.to_string())
+            }
+        }
+    };
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_like_toml() {
+        let yaml = "\
+# my packages
+name: apt
+install: sudo apt-get install -y $
+supports_multi_args: true
+batch_size: 50
+os: [linux, 'freebsd']
+packages:
+  - git   # comment
+  - \"ripgrep@14.1.0\"
+  - name: bat
+    args: [\"--no-install-recommends\"]
+    reason: null
+hosts:
+  laptop: {replace: true, packages: [tlp]}
+";
+        let toml: Value = toml::from_str(
+            r#"
+name = "apt"
+install = "sudo apt-get install -y $"
+supports_multi_args = true
+batch_size = 50
+os = ["linux", "freebsd"]
+packages = ["git", "ripgrep@14.1.0", { name = "bat", args = ["--no-install-recommends"] }]
+hosts = { laptop = { replace = true, packages = ["tlp"] } }
+"#,
+        )
+        .unwrap();
+        assert_eq!(parse(yaml).unwrap(), toml);
+    }
+
+    #[test]
+    fn sequences_at_key_indent_and_block_scalars() {
+        let yaml = "---\nmanagers:\n- apt\n- npm\nscript: |\n  echo one\n    echo two\nnote: >-\n  folded\n  text\n";
+        let value = parse(yaml).unwrap();
+        assert_eq!(
+            value["managers"].as_array().unwrap(),
+            &[Value::from("apt"), Value::from("npm")]
+        );
+        assert_eq!(value["script"].as_str(), Some("echo one\n  echo two\n"));
+        assert_eq!(value["note"].as_str(), Some("folded text"));
+    }
+
+    #[test]
+    fn quoted_scalars_and_comments() {
+        let value =
+            parse("a: 'it''s # not a comment'\nb: \"tab\\there\" # comment\nc: x#y\n").unwrap();
+        assert_eq!(value["a"].as_str(), Some("it's # not a comment"));
+        assert_eq!(value["b"].as_str(), Some("tab\there"));
+        assert_eq!(value["c"].as_str(), Some("x#y"));
+    }
+
+    #[test]
+    fn reports_error_line() {
+        assert_eq!(
+            parse("name: apt\n  install: x\n").unwrap_err(),
+            "unexpected indentation at line 2"
+        );
+        assert_eq!(
+            parse("a: 1\na: 2\n").unwrap_err(),
+            "duplicate key `a` at line 2"
+        );
+        assert!(parse("a: &x 1\n").is_err());
+    }
+}